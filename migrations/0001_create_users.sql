@@ -0,0 +1,8 @@
+-- `if not exists` so databases set up by hand before migrations existed are
+-- adopted as-is.
+create table if not exists users (
+    discord_id bigint primary key,
+    gamertag text not null unique,
+    latest_match_id text,
+    enabled boolean not null default true
+);
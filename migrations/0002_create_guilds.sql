@@ -0,0 +1,11 @@
+-- Per-guild announcement channels and guild membership of registered users.
+create table if not exists guilds (
+    guild_id bigint primary key,
+    channel_id bigint not null
+);
+
+create table if not exists guild_users (
+    guild_id bigint not null,
+    discord_id bigint not null references users (discord_id) on delete cascade,
+    primary key (guild_id, discord_id)
+);
@@ -0,0 +1,66 @@
+-- Every match the bot has processed, with the lobby-wide numbers the embed
+-- compares against.
+create table matches (
+    id text primary key,
+    played_at timestamptz not null,
+    duration_seconds integer not null,
+    category text not null,
+    map_name text not null,
+    map_thumbnail_url text not null,
+    ranked boolean not null,
+    queue text,
+    input text,
+    player_count integer not null,
+    avg_kills double precision not null,
+    avg_damage double precision not null
+);
+
+create index matches_played_at on matches (played_at);
+
+create table match_teams (
+    match_id text not null references matches (id) on delete cascade,
+    team_id integer not null,
+    name text not null,
+    mmr double precision not null,
+    kills integer not null,
+    deaths integer not null,
+    assists integer not null,
+    damage_dealt integer not null,
+    damage_taken integer not null,
+    primary key (match_id, team_id)
+);
+
+-- One row per registered player in a match. `gamertag` is lowercased like
+-- `users.gamertag`.
+create table match_players (
+    match_id text not null references matches (id) on delete cascade,
+    gamertag text not null,
+    team_id integer not null,
+    rank integer not null,
+    outcome text not null,
+    kills integer not null,
+    deaths integer not null,
+    assists integer not null,
+    kda double precision not null,
+    damage_dealt integer not null,
+    damage_taken integer not null,
+    accuracy double precision not null,
+    pre_csr_tier text,
+    pre_csr_sub_tier integer,
+    pre_csr integer,
+    post_csr_tier text,
+    post_csr_sub_tier integer,
+    post_csr integer,
+    primary key (match_id, gamertag)
+);
+
+create index match_players_gamertag on match_players (gamertag);
+
+create table match_player_medals (
+    match_id text not null,
+    gamertag text not null,
+    name text not null,
+    count integer not null,
+    primary key (match_id, gamertag, name),
+    foreign key (match_id, gamertag) references match_players (match_id, gamertag) on delete cascade
+);
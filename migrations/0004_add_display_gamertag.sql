@@ -0,0 +1,4 @@
+-- The gamertag as the Halo API spells it, for display. `gamertag` stays
+-- lowercased since it's what everything else joins on. Null for players
+-- registered before gamertags were verified.
+alter table users add column display_gamertag text;
@@ -0,0 +1,13 @@
+-- Which of a player's matches get announced. A null column means the
+-- default for that setting, so only what the player changed is stored.
+create table user_settings (
+    discord_id bigint primary key references users (discord_id) on delete cascade,
+    categories text[],
+    queues text[],
+    inputs text[],
+    outcomes text[]
+);
+
+-- Custom games are listed separately from matchmade ones, so they're caught
+-- up on separately too.
+alter table users add column latest_custom_match_id text;
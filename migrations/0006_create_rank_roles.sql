@@ -0,0 +1,8 @@
+-- The Discord role each server gives members in a rank tier. Tiers without
+-- a row get no role.
+create table rank_roles (
+    guild_id bigint not null,
+    tier text not null,
+    role_id bigint not null,
+    primary key (guild_id, tier)
+);
@@ -0,0 +1,10 @@
+-- When the last match covered by a posted session summary was played.
+-- Existing players start from their latest stored match so old history
+-- isn't summarized.
+alter table users add column session_summarized_until timestamptz;
+
+update users u set session_summarized_until = (
+    select max(m.played_at)
+    from match_players mp join matches m on m.id = mp.match_id
+    where mp.gamertag = u.gamertag
+);
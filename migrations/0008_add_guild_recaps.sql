@@ -0,0 +1,3 @@
+-- When each guild's weekly recap was last posted, so a restart doesn't post
+-- the same week twice.
+alter table guilds add column last_recap_at timestamptz;
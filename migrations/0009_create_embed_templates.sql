@@ -0,0 +1,6 @@
+-- Which fields each server's match posts show, in order. Servers without a
+-- row get the default layout.
+create table embed_templates (
+    guild_id bigint primary key,
+    elements text[] not null
+);
@@ -0,0 +1,5 @@
+-- Installs upgrading from before matches were routed per server have users
+-- but no guilds. Their old server and channel are backfilled at startup from
+-- `discord.legacy_guild_id` and `discord.legacy_channel_id` instead of here,
+-- so no particular install's IDs are baked into the schema.
+select 1;
@@ -0,0 +1,24 @@
+-- Matches the poller has found but not posted yet. A player's cursor only
+-- moves past a match in the same transaction that queues it here, and it's
+-- only removed once it's been posted, so neither a restart nor a failed post
+-- loses it.
+create table pending_matches (
+    match_id text primary key,
+    played_at timestamptz not null,
+    -- When to next try posting it: once the grouping grace window has
+    -- passed, then backing off after each failed attempt.
+    ready_at timestamptz not null,
+    attempts integer not null default 0,
+    -- Channels it's already been posted in, so retrying after some channels
+    -- failed doesn't post it twice in the others.
+    posted_channels bigint[] not null default '{}'
+);
+
+-- Every registered player seen in a pending match, with their line from
+-- their match list as JSON.
+create table pending_match_players (
+    match_id text not null references pending_matches (match_id) on delete cascade,
+    gamertag text not null,
+    data text not null,
+    primary key (match_id, gamertag)
+);
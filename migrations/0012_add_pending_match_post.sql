@@ -0,0 +1,4 @@
+-- Every unseen match is queued so it gets stored in history, but only the
+-- players who want it posted are announced. Rows queued before this were
+-- all meant to be posted.
+alter table pending_match_players add column post boolean not null default true;
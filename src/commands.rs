@@ -0,0 +1,494 @@
+use crate::halo_api::HaloApi;
+use crate::leaderboard::Sort;
+use crate::lookup;
+use crate::matches_request::Mode;
+use crate::matches_response::Tier;
+use crate::rank;
+use crate::role_sync;
+use crate::stats::Period;
+use serenity::builder::{
+    CreateApplicationCommands, CreateComponents, CreateEmbed, CreateInteractionResponseData,
+    CreateInteractionResponseFollowup,
+};
+use serenity::http::AttachmentType;
+use serenity::model::guild::Member;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+    ApplicationCommandOptionType,
+};
+use serenity::model::interactions::InteractionApplicationCommandCallbackDataFlags;
+use serenity::model::misc::Mentionable;
+use serenity::model::Permissions;
+
+/// How many recent matches to look through for a new player's current rank.
+const RANK_LOOKBACK: usize = 25;
+
+/// Commands that call the Halo API, which can take longer than Discord waits
+/// for a response, so they're deferred and answered with a followup.
+///
+/// Only followups can carry files, so commands that attach one are deferred
+/// too, as is `/rankrole`, which can update every registered member's roles.
+const DEFERRED: [&str; 5] = ["register", "graph", "match", "last", "rankrole"];
+
+pub fn is_deferred(name: &str) -> bool {
+    DEFERRED.contains(&name)
+}
+
+/// What a command replies with.
+#[derive(Default)]
+pub struct Reply {
+    pub content: Option<String>,
+    pub embed: Option<CreateEmbed>,
+    pub components: Option<CreateComponents>,
+    /// Only show the reply to whoever used the command.
+    pub ephemeral: bool,
+    /// Attachments, which are only sent with followups.
+    pub files: Vec<AttachmentType<'static>>,
+}
+
+/// The message builders a `Reply` can fill in: the initial interaction
+/// response and followups. They have the same methods but no shared trait.
+trait ReplyMessage {
+    fn set_content(&mut self, content: String);
+    fn push_embed(&mut self, embed: CreateEmbed);
+    fn set_components(&mut self, components: CreateComponents);
+    fn set_ephemeral(&mut self);
+}
+
+macro_rules! reply_message {
+    ($builder:ty) => {
+        impl ReplyMessage for $builder {
+            fn set_content(&mut self, content: String) {
+                self.content(content);
+            }
+
+            fn push_embed(&mut self, embed: CreateEmbed) {
+                self.add_embed(embed);
+            }
+
+            fn set_components(&mut self, components: CreateComponents) {
+                self.components(|c| {
+                    *c = components;
+                    c
+                });
+            }
+
+            fn set_ephemeral(&mut self) {
+                self.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL);
+            }
+        }
+    };
+}
+
+reply_message!(CreateInteractionResponseData);
+reply_message!(CreateInteractionResponseFollowup<'_>);
+
+impl Reply {
+    /// Fills in everything but the files, which are handed back.
+    fn fill(self, message: &mut impl ReplyMessage) -> Vec<AttachmentType<'static>> {
+        if let Some(content) = self.content {
+            message.set_content(content);
+        }
+        if let Some(embed) = self.embed {
+            message.push_embed(embed);
+        }
+        if let Some(components) = self.components {
+            message.set_components(components);
+        }
+        if self.ephemeral {
+            message.set_ephemeral();
+        }
+        self.files
+    }
+
+    pub fn apply(
+        self,
+        message: &mut CreateInteractionResponseData,
+    ) -> &mut CreateInteractionResponseData {
+        self.fill(message);
+        message
+    }
+
+    pub fn apply_followup<'a, 'b>(
+        self,
+        message: &'b mut CreateInteractionResponseFollowup<'a>,
+    ) -> &'b mut CreateInteractionResponseFollowup<'a> {
+        let files = self.fill(message);
+        message.add_files(files);
+        message
+    }
+}
+
+impl From<String> for Reply {
+    fn from(content: String) -> Self {
+        Reply {
+            content: Some(content),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<CreateEmbed> for Reply {
+    fn from(embed: CreateEmbed) -> Self {
+        Reply {
+            embed: Some(embed),
+            ..Default::default()
+        }
+    }
+}
+
+pub fn create_commands(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|command| {
+            command
+                .name("register")
+                .description("Register yourself for match updates in this server")
+                .create_option(|option| {
+                    option
+                        .name("gamertag")
+                        .description("Your GamerTag")
+                        .kind(ApplicationCommandOptionType::String)
+                        .required(true)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("unregister")
+                .description("Unregister and delete your stored match history")
+                .create_option(|option| {
+                    option
+                        .name("user")
+                        .description("Who to unregister (admin only, defaults to you)")
+                        .kind(ApplicationCommandOptionType::User)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("settings")
+                .description("Choose which of your matches get posted")
+        })
+        .create_application_command(|command| {
+            command
+                .name("toggle")
+                .description("Toggle whether your games are displayed")
+        })
+        .create_application_command(|command| {
+            command
+                .name("setchannel")
+                .description("Post match updates for this server in a channel (admin only)")
+                .create_option(|option| {
+                    option
+                        .name("channel")
+                        .description("The channel to post match updates in")
+                        .kind(ApplicationCommandOptionType::Channel)
+                        .required(true)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("rankrole")
+                .description("Give members a role for a rank tier (admin only)")
+                .create_option(role_sync::create_tier_option)
+                .create_option(|option| {
+                    option
+                        .name("role")
+                        .description("The role to give (leave out to stop giving one)")
+                        .kind(ApplicationCommandOptionType::Role)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("embed")
+                .description("Choose what match posts in this server show (admin only)")
+                .create_option(|option| {
+                    option
+                        .name("fields")
+                        .description("Fields to show in order, like: playlist, rank, kda, medals")
+                        .kind(ApplicationCommandOptionType::String)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("stats")
+                .description("Show how a player has been doing")
+                .create_option(|option| {
+                    option
+                        .name("user")
+                        .description("Who to show stats for (defaults to you)")
+                        .kind(ApplicationCommandOptionType::User)
+                })
+                .create_option(Period::create_option)
+        })
+        .create_application_command(|command| {
+            command
+                .name("compare")
+                .description("Compare two players side by side")
+                .create_option(|option| {
+                    option
+                        .name("user")
+                        .description("Who to compare")
+                        .kind(ApplicationCommandOptionType::User)
+                        .required(true)
+                })
+                .create_option(|option| {
+                    option
+                        .name("against")
+                        .description("Who to compare them with (defaults to you)")
+                        .kind(ApplicationCommandOptionType::User)
+                })
+                .create_option(Period::create_option)
+        })
+        .create_application_command(|command| {
+            command
+                .name("graph")
+                .description("Chart a player's CSR over time")
+                .create_option(|option| {
+                    option
+                        .name("user")
+                        .description("Whose CSR to chart (defaults to you)")
+                        .kind(ApplicationCommandOptionType::User)
+                })
+                .create_option(Period::create_option)
+        })
+        .create_application_command(|command| {
+            command
+                .name("match")
+                .description(format!(
+                    "Show a match by its ID, from the player's last {} games",
+                    lookup::SEARCH_DEPTH
+                ))
+                .create_option(|option| {
+                    option
+                        .name("id")
+                        .description("The match ID")
+                        .kind(ApplicationCommandOptionType::String)
+                        .required(true)
+                })
+                .create_option(|option| {
+                    option
+                        .name("gamertag")
+                        .description(
+                            "A player in the match, who played it recently (defaults to you)",
+                        )
+                        .kind(ApplicationCommandOptionType::String)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("last")
+                .description("Show a player's latest match")
+                .create_option(|option| {
+                    option
+                        .name("gamertag")
+                        .description("Whose match to show (defaults to you)")
+                        .kind(ApplicationCommandOptionType::String)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("leaderboard")
+                .description("Rank everyone on this server")
+                .create_option(Sort::create_option)
+                .create_option(Period::create_option)
+        })
+}
+
+pub fn option<'a>(
+    command: &'a ApplicationCommandInteraction,
+    name: &str,
+) -> Option<&'a ApplicationCommandInteractionDataOptionValue> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.resolved.as_ref())
+}
+
+pub fn is_admin(member: Option<&Member>) -> bool {
+    member
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| {
+            permissions.contains(Permissions::ADMINISTRATOR)
+                || permissions.contains(Permissions::MANAGE_GUILD)
+        })
+}
+
+pub async fn gamertag_for(
+    client: &tokio_postgres::Client,
+    user_id: UserId,
+) -> Result<Option<String>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "select coalesce(display_gamertag, gamertag) from users where discord_id = $1",
+            &[&(user_id.0 as i64)],
+        )
+        .await?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+/// Adds a registered user to a server's match updates. Does nothing for
+/// users who haven't registered.
+pub async fn add_guild_user(
+    client: &tokio_postgres::Client,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            "insert into guild_users (guild_id, discord_id) \
+             select $1, discord_id from users where discord_id = $2 \
+             on conflict do nothing",
+            &[&(guild_id.0 as i64), &(user_id.0 as i64)],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Registers a player after checking the Halo API knows their gamertag, and
+/// replies with their emblem and current rank.
+///
+/// Matches are posted to the servers a player registered or used a command
+/// in, so registering from a DM is refused rather than routing nowhere.
+pub async fn register_gamertag(
+    gamertag: &str,
+    user_id: UserId,
+    guild_id: Option<GuildId>,
+    client: &tokio_postgres::Client,
+    api: &dyn HaloApi,
+) -> Reply {
+    let guild_id = match guild_id {
+        Some(guild_id) => guild_id,
+        None => {
+            return "Register from a server so your matches get posted there"
+                .to_owned()
+                .into()
+        }
+    };
+
+    let matches = match api
+        .get_matches(gamertag, Mode::Matchmade, RANK_LOOKBACK, 0)
+        .await
+    {
+        Ok(matches) => matches,
+        Err(why) if why.is_not_found() => {
+            return format!("Couldn't find a Halo Infinite player called {}", gamertag).into()
+        }
+        Err(why) => {
+            println!("Failed verifying gamertag {}: {}", gamertag, why);
+            return "Couldn't reach the Halo API to check that gamertag, try again later"
+                .to_owned()
+                .into();
+        }
+    };
+    let gamertag = matches.additional.gamertag.as_str();
+
+    let result = client
+        .execute(
+            // A new gamertag's history won't have the old cursors in it, so
+            // they start over like a new player's.
+            "insert into users (discord_id, gamertag, display_gamertag) values ($1, $2, $3) \
+             on conflict (discord_id) do update set gamertag = EXCLUDED.gamertag, \
+             display_gamertag = EXCLUDED.display_gamertag, \
+             latest_match_id = case when users.gamertag = EXCLUDED.gamertag \
+                 then users.latest_match_id end, \
+             latest_custom_match_id = case when users.gamertag = EXCLUDED.gamertag \
+                 then users.latest_custom_match_id end, \
+             session_summarized_until = case when users.gamertag = EXCLUDED.gamertag \
+                 then users.session_summarized_until end",
+            &[&(user_id.0 as i64), &gamertag.to_lowercase(), &gamertag],
+        )
+        .await;
+    if result.is_err() {
+        return format!("Someone has already registered as {}", gamertag).into();
+    }
+
+    if let Err(why) = add_guild_user(client, guild_id, user_id).await {
+        println!("Failed adding user to guild: {}", why);
+        return "Registered, but couldn't add you to this server's updates"
+            .to_owned()
+            .into();
+    }
+
+    let rank = matches
+        .data
+        .iter()
+        .find_map(|game| game.player.progression.as_ref())
+        .map(|progression| {
+            let csr = &progression.csr.post_match;
+            format!(
+                "{} ({})",
+                rank::rank_label(&csr.tier, csr.sub_tier),
+                csr.value
+            )
+        })
+        .unwrap_or_else(|| format!("{} Unranked", rank::rank_emoji(&Tier::Unranked)));
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("Registered {}", gamertag))
+        .color((0, 0, 255))
+        .field("Rank", rank, true);
+    match api.get_emblem(gamertag).await {
+        Ok(emblem) => {
+            embed.thumbnail(emblem.data.emblem_url);
+        }
+        Err(why) => println!("Failed getting emblem for {}: {}", gamertag, why),
+    }
+
+    embed.into()
+}
+
+pub async fn toggle_user(user_id: UserId, client: &tokio_postgres::Client) -> String {
+    let user_id = user_id.0 as i64;
+    let result = client
+        .query(
+            "update users set enabled = not enabled where discord_id = $1 returning enabled",
+            &[&user_id],
+        )
+        .await;
+    match result.as_deref().map(|rows| rows.first()) {
+        Ok(Some(row)) if row.get::<_, bool>(0) => "Your matches will now be shown again".to_owned(),
+        Ok(Some(_)) => "You will no longer see your matches".to_owned(),
+        Ok(None) => "You aren't registered yet, use /register first".to_owned(),
+        Err(why) => {
+            println!("Failed toggling user: {}", why);
+            "Couldn't toggle your matches".to_owned()
+        }
+    }
+}
+
+pub async fn set_channel(
+    command: &ApplicationCommandInteraction,
+    client: &tokio_postgres::Client,
+) -> String {
+    let guild_id = match command.guild_id {
+        Some(guild_id) => guild_id,
+        None => return "This command can only be used in a server".to_owned(),
+    };
+
+    if !is_admin(command.member.as_ref()) {
+        return "Only server admins can change the match update channel".to_owned();
+    }
+
+    let channel_id = match option(command, "channel") {
+        Some(ApplicationCommandInteractionDataOptionValue::Channel(channel)) => channel.id,
+        _ => unreachable!("Command type"),
+    };
+
+    let result = client
+        .execute(
+            "insert into guilds (guild_id, channel_id) values ($1, $2) on conflict (guild_id) do update set channel_id = EXCLUDED.channel_id",
+            &[&(guild_id.0 as i64), &(channel_id.0 as i64)],
+        )
+        .await;
+
+    match result {
+        Ok(_) => format!("Match updates will be posted in {}", channel_id.mention()),
+        Err(why) => {
+            println!("Failed setting channel: {}", why);
+            "Couldn't save the match update channel".to_owned()
+        }
+    }
+}
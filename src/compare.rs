@@ -0,0 +1,309 @@
+use crate::commands::{self, Reply};
+use crate::config::Config;
+use crate::error::Result;
+use crate::rank::StoredRank;
+use crate::stats::{self, Period, PlayerStats};
+use chrono::{DateTime, Utc};
+use serenity::builder::CreateEmbed;
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+};
+use serenity::model::user::User;
+use tokio_postgres::Client;
+
+/// One player's side of a comparison.
+struct Side {
+    gamertag: String,
+    stats: PlayerStats,
+    medals: i64,
+    /// Their rank after their latest stored ranked match, from any time.
+    rank: Option<StoredRank>,
+}
+
+impl Side {
+    fn medal_rate(&self) -> f64 {
+        self.medals as f64 / self.stats.games.max(1) as f64
+    }
+}
+
+/// How two players did in the matches they both played.
+struct HeadToHead {
+    together: i64,
+    together_wins: i64,
+    together_losses: i64,
+    against: i64,
+    first_wins: i64,
+    second_wins: i64,
+}
+
+async fn medal_count(client: &Client, gamertag: &str, since: Option<DateTime<Utc>>) -> Result<i64> {
+    let row = client
+        .query_one(
+            "select coalesce(sum(mpm.count), 0) from match_player_medals mpm \
+             join matches m on m.id = mpm.match_id \
+             where mpm.gamertag = $1 and ($2::timestamptz is null or m.played_at >= $2)",
+            &[&gamertag.to_lowercase(), &since],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+async fn latest_rank(client: &Client, gamertag: &str) -> Result<Option<StoredRank>> {
+    let row = client
+        .query_opt(
+            "select mp.post_csr_tier, mp.post_csr_sub_tier, mp.post_csr \
+             from match_players mp join matches m on m.id = mp.match_id \
+             where mp.gamertag = $1 and m.ranked and mp.post_csr is not null \
+             order by m.played_at desc limit 1",
+            &[&gamertag.to_lowercase()],
+        )
+        .await?;
+    Ok(row.and_then(|row| StoredRank::from_row(&row, 0)))
+}
+
+async fn head_to_head(
+    client: &Client,
+    first: &str,
+    second: &str,
+    since: Option<DateTime<Utc>>,
+) -> Result<HeadToHead> {
+    let row = client
+        .query_one(
+            "select count(*) filter (where a.team_id = b.team_id), \
+             count(*) filter (where a.team_id = b.team_id and a.outcome = 'win'), \
+             count(*) filter (where a.team_id = b.team_id and a.outcome = 'loss'), \
+             count(*) filter (where a.team_id <> b.team_id), \
+             count(*) filter (where a.team_id <> b.team_id and a.outcome = 'win'), \
+             count(*) filter (where a.team_id <> b.team_id and b.outcome = 'win') \
+             from match_players a \
+             join match_players b on b.match_id = a.match_id \
+             join matches m on m.id = a.match_id \
+             where a.gamertag = $1 and b.gamertag = $2 \
+             and ($3::timestamptz is null or m.played_at >= $3)",
+            &[&first.to_lowercase(), &second.to_lowercase(), &since],
+        )
+        .await?;
+
+    Ok(HeadToHead {
+        together: row.get(0),
+        together_wins: row.get(1),
+        together_losses: row.get(2),
+        against: row.get(3),
+        first_wins: row.get(4),
+        second_wins: row.get(5),
+    })
+}
+
+async fn load_side(
+    client: &Client,
+    gamertag: String,
+    since: Option<DateTime<Utc>>,
+) -> Result<Option<Side>> {
+    let stats = match stats::player_stats(client, &gamertag, since).await? {
+        Some(stats) => stats,
+        None => return Ok(None),
+    };
+    let medals = medal_count(client, &gamertag, since).await?;
+    let rank = latest_rank(client, &gamertag).await?;
+
+    Ok(Some(Side {
+        gamertag,
+        stats,
+        medals,
+        rank,
+    }))
+}
+
+/// Looks up a user's side, or says why there isn't one.
+async fn side(
+    client: &Client,
+    user: &User,
+    period: Period,
+    config: &Config,
+) -> std::result::Result<Side, String> {
+    let gamertag = match commands::gamertag_for(client, user.id).await {
+        Ok(Some(gamertag)) => gamertag,
+        Ok(None) => return Err(format!("{} hasn't registered a gamertag", user.name)),
+        Err(why) => {
+            println!("Failed looking up gamertag: {}", why);
+            return Err("Couldn't look up that player".to_owned());
+        }
+    };
+
+    match load_side(client, gamertag.clone(), period.since(config)).await {
+        Ok(Some(side)) => Ok(side),
+        Ok(None) => Err(format!(
+            "{} has no stored games for {}",
+            gamertag,
+            period.describe()
+        )),
+        Err(why) => {
+            println!("Failed getting stats for {}: {}", gamertag, why);
+            Err("Couldn't get stats right now".to_owned())
+        }
+    }
+}
+
+/// Both values side by side, with the better one in bold.
+fn versus(first: f64, second: f64, format: impl Fn(f64) -> String) -> String {
+    let (first_text, second_text) = (format(first), format(second));
+    if first > second {
+        format!("**{}** vs {}", first_text, second_text)
+    } else if second > first {
+        format!("{} vs **{}**", first_text, second_text)
+    } else {
+        format!("{} vs {}", first_text, second_text)
+    }
+}
+
+/// The player's current rank, and their CSR change in the playlist they
+/// played ranked most recently.
+fn rank_line(side: &Side) -> String {
+    let rank = side
+        .rank
+        .map_or_else(|| "Unranked".to_owned(), |rank| rank.label());
+    match side.stats.csr_changes.first() {
+        Some((playlist, change)) => format!(
+            "{}: {}, {} CSR in {}",
+            side.gamertag,
+            rank,
+            stats::format_change(*change),
+            playlist
+        ),
+        None => format!("{}: {}", side.gamertag, rank),
+    }
+}
+
+pub async fn compare_command(
+    command: &ApplicationCommandInteraction,
+    client: &Client,
+    config: &Config,
+) -> Reply {
+    let first = match commands::option(command, "user") {
+        Some(ApplicationCommandInteractionDataOptionValue::User(user, _)) => user,
+        _ => unreachable!("Command type"),
+    };
+    let second = match commands::option(command, "against") {
+        Some(ApplicationCommandInteractionDataOptionValue::User(user, _)) => user,
+        _ => &command.user,
+    };
+    if first.id == second.id {
+        return "Pick two different players to compare".to_owned().into();
+    }
+    let period = match commands::option(command, "period") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(period)) => {
+            Period::from_option(Some(period))
+        }
+        _ => Period::from_option(None),
+    };
+
+    let first = match side(client, first, period, config).await {
+        Ok(side) => side,
+        Err(message) => return message.into(),
+    };
+    let second = match side(client, second, period, config).await {
+        Ok(side) => side,
+        Err(message) => return message.into(),
+    };
+
+    let record = match head_to_head(
+        client,
+        &first.gamertag,
+        &second.gamertag,
+        period.since(config),
+    )
+    .await
+    {
+        Ok(record) => record,
+        Err(why) => {
+            println!(
+                "Failed getting head to head for {} and {}: {}",
+                first.gamertag, second.gamertag, why
+            );
+            return "Couldn't get stats right now".to_owned().into();
+        }
+    };
+
+    let (a, b) = (&first.stats, &second.stats);
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!(
+            "{} vs {} for {}",
+            first.gamertag,
+            second.gamertag,
+            period.describe()
+        ))
+        .color((0, 0, 255))
+        .field(
+            "Games",
+            versus(a.games as f64, b.games as f64, |games| games.to_string()),
+            true,
+        )
+        .field(
+            "Win rate",
+            versus(a.win_rate(), b.win_rate(), |rate| format!("{:.0}%", rate)),
+            true,
+        )
+        .field(
+            "KDA",
+            versus(a.avg_kda, b.avg_kda, |kda| format!("{:.1}", kda)),
+            true,
+        )
+        .field(
+            "KPM",
+            versus(a.kpm(), b.kpm(), |kpm| format!("{:.2}", kpm)),
+            true,
+        )
+        .field(
+            "Accuracy",
+            versus(a.avg_accuracy, b.avg_accuracy, |accuracy| {
+                format!("{}%", accuracy.round())
+            }),
+            true,
+        )
+        .field(
+            "Damage per game",
+            versus(a.avg_damage, b.avg_damage, |damage| {
+                damage.round().to_string()
+            }),
+            true,
+        )
+        .field(
+            "Medals per game",
+            versus(first.medal_rate(), second.medal_rate(), |rate| {
+                format!("{:.1}", rate)
+            }),
+            true,
+        )
+        .field(
+            "CSR",
+            format!("{}\n{}", rank_line(&first), rank_line(&second)),
+            false,
+        )
+        .field(
+            "Same team",
+            if record.together == 0 {
+                "No games together".to_owned()
+            } else {
+                format!(
+                    "{}/{} in {} games",
+                    record.together_wins, record.together_losses, record.together
+                )
+            },
+            true,
+        )
+        .field(
+            "Against each other",
+            if record.against == 0 {
+                "No games against each other".to_owned()
+            } else {
+                format!(
+                    "{} {} – {} {}",
+                    first.gamertag, record.first_wins, record.second_wins, second.gamertag
+                )
+            },
+            true,
+        );
+
+    embed.into()
+}
@@ -0,0 +1,528 @@
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_CONFIG_PATH: &str = "cortana.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub discord: DiscordConfig,
+    #[serde(default)]
+    pub halo: HaloConfig,
+    #[serde(default)]
+    pub poller: PollerConfig,
+    #[serde(default)]
+    pub database: DatabaseConfig,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    #[serde(default)]
+    pub roles: RolesConfig,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    #[serde(default)]
+    pub recap: RecapConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordConfig {
+    #[serde(default)]
+    pub application_id: u64,
+    #[serde(default)]
+    pub command_scope: CommandScope,
+    /// The server and channel every match was posted to before matches were
+    /// routed per server. Set both when upgrading an install from then, so
+    /// its players keep getting posted there.
+    #[serde(default)]
+    pub legacy_guild_id: Option<u64>,
+    #[serde(default)]
+    pub legacy_channel_id: Option<u64>,
+}
+
+/// Where slash commands are registered. Guild commands show up immediately
+/// in every guild the bot is in; global commands can take up to an hour to
+/// propagate but don't need re-registering when the bot joins a guild.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandScope {
+    #[default]
+    Guild,
+    Global,
+}
+
+impl FromStr for CommandScope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "guild" => Ok(CommandScope::Guild),
+            "global" => Ok(CommandScope::Global),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HaloConfig {
+    pub base_url: String,
+    pub version: String,
+    /// Replay recorded responses from this directory instead of calling the
+    /// API. See `FixtureHaloApi` for the layout.
+    pub fixtures_dir: Option<PathBuf>,
+    /// The most API requests allowed in flight at once.
+    pub max_in_flight: usize,
+    /// How many times a rate limited or transiently failing request is
+    /// retried before giving up.
+    pub max_retries: u32,
+    pub backoff_base_ms: u64,
+    pub backoff_max_ms: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for HaloConfig {
+    fn default() -> Self {
+        HaloConfig {
+            base_url: "https://halo.api.stdlib.com".to_owned(),
+            version: "infinite@0.3.8".to_owned(),
+            fixtures_dir: None,
+            max_in_flight: 4,
+            max_retries: 3,
+            backoff_base_ms: 500,
+            backoff_max_ms: 30_000,
+            timeout_secs: 30,
+        }
+    }
+}
+
+impl HaloConfig {
+    pub fn endpoint(&self, path: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            self.base_url.trim_end_matches('/'),
+            self.version,
+            path
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollerConfig {
+    pub interval_secs: u64,
+    /// The most matches to post for one player after a long gap, e.g. when
+    /// the bot has been down. Older unseen matches are skipped.
+    pub max_catch_up: usize,
+    /// How long to hold a new match for other registered players in it to
+    /// show up, so they're posted together in one embed.
+    pub group_grace_secs: u64,
+}
+
+impl Default for PollerConfig {
+    fn default() -> Self {
+        PollerConfig {
+            interval_secs: 5,
+            max_catch_up: 25,
+            group_grace_secs: 15,
+        }
+    }
+}
+
+impl PollerConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    pub fn group_grace(&self) -> Duration {
+        Duration::from_secs(self.group_grace_secs)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub ca_file: PathBuf,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            ca_file: PathBuf::from("ca-certificate.crt"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsConfig {
+    /// When the current ranked season started, for the "season" period.
+    pub season_start: DateTime<Utc>,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            season_start: Utc.ymd(2021, 11, 15).and_hms(18, 0, 0),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RolesConfig {
+    /// How often every member's rank role is re-synced, on top of the sync
+    /// after each of their matches.
+    pub sweep_interval_secs: u64,
+}
+
+impl Default for RolesConfig {
+    fn default() -> Self {
+        RolesConfig {
+            sweep_interval_secs: 60 * 60,
+        }
+    }
+}
+
+impl RolesConfig {
+    pub fn sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.sweep_interval_secs)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsConfig {
+    /// The longest break between two matches of the same session. A session
+    /// is summarized once a player has gone this long without a match.
+    pub gap_mins: u64,
+    /// Sessions with fewer matches aren't summarized.
+    pub min_games: usize,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        SessionsConfig {
+            gap_mins: 30,
+            min_games: 2,
+        }
+    }
+}
+
+impl SessionsConfig {
+    pub fn gap(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.gap_mins as i64)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecapConfig {
+    /// The hour (UTC) on Mondays that the weekly recap is posted.
+    pub hour_utc: u32,
+    /// The fewest games to qualify for the best win rate.
+    pub min_games: i64,
+}
+
+impl Default for RecapConfig {
+    fn default() -> Self {
+        RecapConfig {
+            hour_utc: 17,
+            min_games: 5,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env(&'static str, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, why) => {
+                write!(f, "could not read config file {}: {}", path.display(), why)
+            }
+            ConfigError::Parse(path, why) => {
+                write!(f, "invalid config file {}: {}", path.display(), why)
+            }
+            ConfigError::Env(var, value) => {
+                write!(
+                    f,
+                    "invalid value {:?} for environment variable {}",
+                    value, var
+                )
+            }
+            ConfigError::Invalid(why) => write!(f, "invalid config: {}", why),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    /// Loads the config file named by `CORTANA_CONFIG` (or `cortana.toml`),
+    /// applies `CORTANA_*` environment overrides and validates the result.
+    ///
+    /// A missing default config file is not an error so that the bot can be
+    /// configured through the environment alone.
+    pub fn load() -> Result<Config, ConfigError> {
+        let (path, required) = match env::var("CORTANA_CONFIG") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+
+        let mut config = if required || path.exists() {
+            Config::from_file(&path)?
+        } else {
+            Config::default()
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|why| ConfigError::Read(path.to_owned(), why))?;
+        toml::from_str(&contents).map_err(|why| ConfigError::Parse(path.to_owned(), why))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        override_from_env(
+            "CORTANA_DISCORD_APPLICATION_ID",
+            &mut self.discord.application_id,
+        )?;
+        override_from_env(
+            "CORTANA_DISCORD_COMMAND_SCOPE",
+            &mut self.discord.command_scope,
+        )?;
+        override_option_from_env(
+            "CORTANA_DISCORD_LEGACY_GUILD_ID",
+            &mut self.discord.legacy_guild_id,
+        )?;
+        override_option_from_env(
+            "CORTANA_DISCORD_LEGACY_CHANNEL_ID",
+            &mut self.discord.legacy_channel_id,
+        )?;
+        override_from_env("CORTANA_HALO_BASE_URL", &mut self.halo.base_url)?;
+        override_from_env("CORTANA_HALO_VERSION", &mut self.halo.version)?;
+        override_from_env("CORTANA_HALO_MAX_IN_FLIGHT", &mut self.halo.max_in_flight)?;
+        override_from_env("CORTANA_HALO_MAX_RETRIES", &mut self.halo.max_retries)?;
+        override_from_env(
+            "CORTANA_HALO_BACKOFF_BASE_MS",
+            &mut self.halo.backoff_base_ms,
+        )?;
+        override_from_env("CORTANA_HALO_BACKOFF_MAX_MS", &mut self.halo.backoff_max_ms)?;
+        override_from_env("CORTANA_HALO_TIMEOUT_SECS", &mut self.halo.timeout_secs)?;
+        override_from_env("CORTANA_POLL_INTERVAL_SECS", &mut self.poller.interval_secs)?;
+        override_from_env("CORTANA_POLL_MAX_CATCH_UP", &mut self.poller.max_catch_up)?;
+        override_from_env(
+            "CORTANA_POLL_GROUP_GRACE_SECS",
+            &mut self.poller.group_grace_secs,
+        )?;
+        override_from_env("CORTANA_DATABASE_CA_FILE", &mut self.database.ca_file)?;
+        override_from_env("CORTANA_STATS_SEASON_START", &mut self.stats.season_start)?;
+        override_from_env(
+            "CORTANA_ROLES_SWEEP_INTERVAL_SECS",
+            &mut self.roles.sweep_interval_secs,
+        )?;
+        override_from_env("CORTANA_SESSIONS_GAP_MINS", &mut self.sessions.gap_mins)?;
+        override_from_env("CORTANA_SESSIONS_MIN_GAMES", &mut self.sessions.min_games)?;
+        override_from_env("CORTANA_RECAP_HOUR_UTC", &mut self.recap.hour_utc)?;
+        override_from_env("CORTANA_RECAP_MIN_GAMES", &mut self.recap.min_games)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        require_id(
+            self.discord.application_id,
+            "discord.application_id",
+            "CORTANA_DISCORD_APPLICATION_ID",
+        )?;
+        if self.discord.legacy_guild_id.is_some() != self.discord.legacy_channel_id.is_some() {
+            return Err(ConfigError::Invalid(
+                "discord.legacy_guild_id and discord.legacy_channel_id must be set together"
+                    .to_owned(),
+            ));
+        }
+
+        if !self.halo.base_url.starts_with("http://") && !self.halo.base_url.starts_with("https://")
+        {
+            return Err(ConfigError::Invalid(format!(
+                "halo.base_url must be an http(s) URL, got {:?}",
+                self.halo.base_url
+            )));
+        }
+        if self.halo.version.is_empty() {
+            return Err(ConfigError::Invalid(
+                "halo.version must not be empty".to_owned(),
+            ));
+        }
+        if self.halo.max_in_flight == 0 {
+            return Err(ConfigError::Invalid(
+                "halo.max_in_flight must be at least 1".to_owned(),
+            ));
+        }
+        if self.halo.timeout_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "halo.timeout_secs must be at least 1".to_owned(),
+            ));
+        }
+        if let Some(dir) = &self.halo.fixtures_dir {
+            if !dir.is_dir() {
+                return Err(ConfigError::Invalid(format!(
+                    "halo.fixtures_dir {} is not a directory",
+                    dir.display()
+                )));
+            }
+        }
+        if self.poller.interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "poller.interval_secs must be at least 1".to_owned(),
+            ));
+        }
+        if self.poller.max_catch_up == 0 {
+            return Err(ConfigError::Invalid(
+                "poller.max_catch_up must be at least 1".to_owned(),
+            ));
+        }
+        if self.roles.sweep_interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "roles.sweep_interval_secs must be at least 1".to_owned(),
+            ));
+        }
+        if self.sessions.gap_mins == 0 {
+            return Err(ConfigError::Invalid(
+                "sessions.gap_mins must be at least 1".to_owned(),
+            ));
+        }
+        if self.recap.hour_utc > 23 {
+            return Err(ConfigError::Invalid(format!(
+                "recap.hour_utc must be an hour from 0 to 23, got {}",
+                self.recap.hour_utc
+            )));
+        }
+        if !self.database.ca_file.is_file() {
+            return Err(ConfigError::Invalid(format!(
+                "database.ca_file {} does not exist",
+                self.database.ca_file.display()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn override_from_env<T: FromStr>(var: &'static str, target: &mut T) -> Result<(), ConfigError> {
+    if let Ok(value) = env::var(var) {
+        *target = value.parse().map_err(|_| ConfigError::Env(var, value))?;
+    }
+    Ok(())
+}
+
+fn override_option_from_env<T: FromStr>(
+    var: &'static str,
+    target: &mut Option<T>,
+) -> Result<(), ConfigError> {
+    if let Ok(value) = env::var(var) {
+        *target = Some(value.parse().map_err(|_| ConfigError::Env(var, value))?);
+    }
+    Ok(())
+}
+
+fn require_id(id: u64, key: &str, var: &str) -> Result<(), ConfigError> {
+    if id == 0 {
+        Err(ConfigError::Invalid(format!(
+            "{} is not set (set it in the config file or with {})",
+            key, var
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example config, with a CA file that exists so it validates.
+    fn example() -> Config {
+        let mut config: Config = toml::from_str(include_str!("../cortana.example.toml")).unwrap();
+        config.database.ca_file = PathBuf::from("Cargo.toml");
+        config
+    }
+
+    #[test]
+    fn the_example_config_loads_and_validates() {
+        let config = example();
+
+        assert_eq!(config.discord.application_id, 928312197489229825);
+        assert_eq!(config.discord.command_scope, CommandScope::Guild);
+        assert_eq!(config.discord.legacy_guild_id, None);
+        assert_eq!(config.poller.max_catch_up, 25);
+        assert_eq!(config.recap.hour_utc, 17);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let result: Result<Config, _> = toml::from_str("[poller]\ninterval = 5\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let mut config = example();
+        env::set_var("CORTANA_POLL_MAX_CATCH_UP", "7");
+        env::set_var("CORTANA_DISCORD_LEGACY_GUILD_ID", "42");
+        let result = config.apply_env();
+        env::remove_var("CORTANA_POLL_MAX_CATCH_UP");
+        env::remove_var("CORTANA_DISCORD_LEGACY_GUILD_ID");
+        result.unwrap();
+
+        assert_eq!(config.poller.max_catch_up, 7);
+        assert_eq!(config.discord.legacy_guild_id, Some(42));
+        assert_eq!(config.poller.interval_secs, 5);
+
+        env::set_var("CORTANA_RECAP_HOUR_UTC", "noon");
+        let result = config.apply_env();
+        env::remove_var("CORTANA_RECAP_HOUR_UTC");
+        assert!(matches!(
+            result,
+            Err(ConfigError::Env("CORTANA_RECAP_HOUR_UTC", _))
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_bad_values() {
+        let invalid = |change: fn(&mut Config)| {
+            let mut config = example();
+            change(&mut config);
+            matches!(config.validate(), Err(ConfigError::Invalid(_)))
+        };
+
+        assert!(invalid(|config| config.discord.application_id = 0));
+        assert!(invalid(|config| config.discord.legacy_guild_id = Some(1)));
+        assert!(invalid(
+            |config| config.halo.base_url = "halo.api".to_owned()
+        ));
+        assert!(invalid(|config| config.poller.max_catch_up = 0));
+        assert!(invalid(|config| config.recap.hour_utc = 24));
+        assert!(invalid(
+            |config| config.database.ca_file = PathBuf::from("missing.crt")
+        ));
+    }
+}
@@ -0,0 +1,533 @@
+use crate::commands::{self, Reply};
+use crate::error::Result;
+use crate::match_card::{self, Averages, MatchCard, MatchContext};
+use crate::matches_response::{
+    Breakdowns, Category, CoreStats, Csr, CsrResult, Damage, Data, Details, Duration, GameMap,
+    Input, MapAsset, Medal, Outcome, Player, Playlist, Progression, Properties, Queue, Shots,
+    Stats, Summary, Team, Tier,
+};
+use chrono::Utc;
+use serenity::builder::CreateComponents;
+use serenity::model::id::GuildId;
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+};
+use serenity::model::interactions::message_component::{ButtonStyle, MessageComponentInteraction};
+use tokio_postgres::Client;
+
+/// Something a match post can show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Element {
+    Playlist,
+    Map,
+    Rank,
+    Kda,
+    Projection,
+    CsrChange,
+    Kpm,
+    Accuracy,
+    Damage,
+    Medals,
+    TeamAverages,
+    MapImage,
+    Emblem,
+}
+
+impl Element {
+    const ALL: [Element; 13] = [
+        Element::Playlist,
+        Element::Map,
+        Element::Rank,
+        Element::Kda,
+        Element::Projection,
+        Element::CsrChange,
+        Element::Kpm,
+        Element::Accuracy,
+        Element::Damage,
+        Element::Medals,
+        Element::TeamAverages,
+        Element::MapImage,
+        Element::Emblem,
+    ];
+
+    /// Also how the element is stored and typed into `/embed`.
+    fn as_str(&self) -> &'static str {
+        match self {
+            Element::Playlist => "playlist",
+            Element::Map => "map",
+            Element::Rank => "rank",
+            Element::Kda => "kda",
+            Element::Projection => "projection",
+            Element::CsrChange => "csr-change",
+            Element::Kpm => "kpm",
+            Element::Accuracy => "accuracy",
+            Element::Damage => "damage",
+            Element::Medals => "medals",
+            Element::TeamAverages => "team-averages",
+            Element::MapImage => "map-image",
+            Element::Emblem => "emblem",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<Element> {
+        Element::ALL
+            .iter()
+            .copied()
+            .find(|element| element.as_str() == value)
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Element::Playlist => "Playlist",
+            Element::Map => "Map name",
+            Element::Rank => "Rank",
+            Element::Kda => "KDA",
+            Element::Projection => "Projected to win",
+            Element::CsrChange => "CSR change",
+            Element::Kpm => "KPM",
+            Element::Accuracy => "Accuracy",
+            Element::Damage => "Damage",
+            Element::Medals => "Medals",
+            Element::TeamAverages => "Team averages",
+            Element::MapImage => "Map image",
+            Element::Emblem => "Emblem",
+        }
+    }
+}
+
+/// Which elements a server's match posts show, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    elements: Vec<Element>,
+}
+
+impl Default for Template {
+    /// Everything but the map name, which the map image already shows.
+    fn default() -> Template {
+        Template {
+            elements: Element::ALL
+                .iter()
+                .copied()
+                .filter(|element| *element != Element::Map)
+                .collect(),
+        }
+    }
+}
+
+impl Template {
+    pub fn new(elements: Vec<Element>) -> Template {
+        Template { elements }
+    }
+
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    pub fn shows(&self, element: Element) -> bool {
+        self.elements.contains(&element)
+    }
+
+    /// Parses a comma separated list like "rank, kda, medals", keeping its
+    /// order. Returns the first name it doesn't know on failure.
+    fn parse(list: &str) -> std::result::Result<Template, String> {
+        let mut elements = Vec::new();
+        for name in list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let element = Element::from_str(&name.to_lowercase().replace(' ', "-"))
+                .ok_or_else(|| name.to_owned())?;
+            if !elements.contains(&element) {
+                elements.push(element);
+            }
+        }
+        Ok(Template { elements })
+    }
+
+    /// Keeps only `selected`, in the current order, with newly selected
+    /// elements added at the end.
+    fn select(&self, selected: &[Element]) -> Template {
+        let mut elements: Vec<Element> = self
+            .elements
+            .iter()
+            .copied()
+            .filter(|element| selected.contains(element))
+            .collect();
+        for element in Element::ALL {
+            if selected.contains(&element) && !elements.contains(&element) {
+                elements.push(element);
+            }
+        }
+        Template { elements }
+    }
+
+    fn describe(&self) -> String {
+        if self.elements.is_empty() {
+            return "nothing but the result".to_owned();
+        }
+        self.elements
+            .iter()
+            .map(|element| element.label())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// A server's template, or the default if it hasn't set one.
+pub async fn load(client: &Client, guild_id: GuildId) -> Result<Template> {
+    let row = client
+        .query_opt(
+            "select elements from embed_templates where guild_id = $1",
+            &[&(guild_id.0 as i64)],
+        )
+        .await?;
+
+    Ok(match row {
+        Some(row) => Template::new(
+            row.get::<_, Vec<&str>>(0)
+                .into_iter()
+                .filter_map(Element::from_str)
+                .collect(),
+        ),
+        None => Template::default(),
+    })
+}
+
+/// Saves a server's template, or goes back to the default for `None`.
+async fn save(client: &Client, guild_id: GuildId, template: Option<&Template>) -> Result<()> {
+    match template {
+        Some(template) => {
+            let elements: Vec<&str> = template
+                .elements
+                .iter()
+                .map(|element| element.as_str())
+                .collect();
+            client
+                .execute(
+                    "insert into embed_templates (guild_id, elements) values ($1, $2) \
+                     on conflict (guild_id) do update set elements = EXCLUDED.elements",
+                    &[&(guild_id.0 as i64), &elements],
+                )
+                .await?;
+        }
+        None => {
+            client
+                .execute(
+                    "delete from embed_templates where guild_id = $1",
+                    &[&(guild_id.0 as i64)],
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// A made-up ranked match to preview templates with, so previews don't need
+/// the Halo API.
+fn sample_card(template: &Template) -> MatchCard {
+    const ASSETS: &str = "https://assets.halo.autocode.gg/static/infinite/images/multiplayer";
+    let csr = |value, tier_start, sub_tier| CsrResult {
+        tier: Tier::Diamond,
+        value,
+        tier_start,
+        sub_tier,
+        tier_image_url: format!("{}/playlist-csrs/diamond.png", ASSETS),
+    };
+    let medal = |name: &str, count| Medal {
+        name: name.to_owned(),
+        count,
+    };
+
+    let data = Data {
+        id: "00000000-0000-0000-0000-000000000000".to_owned(),
+        details: Details {
+            category: Category {
+                name: "Strongholds".to_owned(),
+            },
+            map: GameMap {
+                name: "Streets".to_owned(),
+                asset: MapAsset {
+                    thumbnail_url: format!("{}/maps/streets.jpg", ASSETS),
+                },
+            },
+            playlist: Some(Playlist {
+                name: "Ranked Arena".to_owned(),
+                properties: Properties {
+                    queue: Some(Queue::Open),
+                    input: Some(Input::Crossplay),
+                    ranked: true,
+                },
+            }),
+        },
+        player: Player {
+            stats: Stats {
+                core: CoreStats {
+                    summary: Summary {
+                        kills: 19,
+                        deaths: 11,
+                        assists: 7,
+                    },
+                    damage: Damage {
+                        taken: 3398,
+                        dealt: 5124,
+                    },
+                    shots: Shots { accuracy: 52.25 },
+                    breakdowns: Breakdowns {
+                        medals: vec![medal("Double Kill", 3), medal("Killing Spree", 1)],
+                    },
+                    kda: 10.33,
+                },
+            },
+            rank: 1,
+            outcome: Outcome::Win,
+            progression: Some(Progression {
+                csr: Csr {
+                    pre_match: csr(1396, 1350, 5),
+                    post_match: csr(1410, 1400, 6),
+                },
+            }),
+            team: Team { id: 0 },
+        },
+        played_at: Utc::now(),
+        duration: Duration { seconds: 721 },
+    };
+    let context = MatchContext {
+        gamertag: "Spartan".to_owned(),
+        emblem_url: Some(format!(
+            "{}/emblems/olympus-spartan-emblem-c4bc1c36.png",
+            ASSETS
+        )),
+        projected_to_win: Some(true),
+        lobby: Averages {
+            damage: 4014,
+            kills: 12.9,
+            kpm: 1.1,
+        },
+        teams: Some((
+            Averages {
+                damage: 4255,
+                kills: 13.8,
+                kpm: 1.1,
+            },
+            Averages {
+                damage: 3774,
+                kills: 12.0,
+                kpm: 1.0,
+            },
+        )),
+    };
+    match_card::render_match_embed(&data, &context, template)
+}
+
+/// Every element's name as typed into `/embed`.
+fn element_names() -> String {
+    Element::ALL
+        .iter()
+        .map(|element| element.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+const SELECT_ID: &str = "embed:select";
+const RESET_ID: &str = "embed:reset";
+
+/// What an `/embed` menu or button does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Select,
+    Reset,
+}
+
+pub fn parse_custom_id(custom_id: &str) -> Option<Action> {
+    match custom_id {
+        SELECT_ID => Some(Action::Select),
+        RESET_ID => Some(Action::Reset),
+        _ => None,
+    }
+}
+
+/// The template with a preview, a menu to pick elements and a reset button.
+fn template_reply(template: &Template, content: &str) -> Reply {
+    let mut components = CreateComponents::default();
+    components
+        .create_action_row(|row| {
+            row.create_select_menu(|menu| {
+                menu.custom_id(SELECT_ID)
+                    .placeholder("Fields to show")
+                    .min_values(0)
+                    .max_values(Element::ALL.len() as u64)
+                    .options(|options| {
+                        for element in Element::ALL {
+                            options.create_option(|option| {
+                                option
+                                    .label(element.label())
+                                    .value(element.as_str())
+                                    .default_selection(template.shows(element))
+                            });
+                        }
+                        options
+                    })
+            })
+        })
+        .create_action_row(|row| {
+            row.create_button(|button| {
+                button
+                    .style(ButtonStyle::Secondary)
+                    .label("Reset to default")
+                    .custom_id(RESET_ID)
+            })
+        });
+
+    let content = format!(
+        "{}\nMatch posts here show: {}.\nPick fields below, or set them in order with \
+         `/embed fields:` using {}.",
+        content,
+        template.describe(),
+        element_names()
+    );
+
+    Reply {
+        content: Some(content),
+        embed: Some(sample_card(template).to_embed()),
+        components: Some(components),
+        ephemeral: true,
+        ..Default::default()
+    }
+}
+
+/// Shows the server's template, after saving the `fields` option if given.
+pub async fn embed_command(command: &ApplicationCommandInteraction, client: &Client) -> Reply {
+    let guild_id = match command.guild_id {
+        Some(guild_id) => guild_id,
+        None => {
+            return "This command can only be used in a server"
+                .to_owned()
+                .into()
+        }
+    };
+
+    if !commands::is_admin(command.member.as_ref()) {
+        return "Only server admins can change match posts"
+            .to_owned()
+            .into();
+    }
+
+    let content = match commands::option(command, "fields") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(list)) => {
+            let template = match Template::parse(list) {
+                Ok(template) => template,
+                Err(name) => {
+                    return format!(
+                        "There's no field called {}, pick from {}",
+                        name,
+                        element_names()
+                    )
+                    .into()
+                }
+            };
+            if let Err(why) = save(client, guild_id, Some(&template)).await {
+                println!("Failed saving embed template: {}", why);
+                return "Couldn't save the match post fields".to_owned().into();
+            }
+            "Saved."
+        }
+        _ => "Choose what match posts in this server show.",
+    };
+
+    match load(client, guild_id).await {
+        Ok(template) => template_reply(&template, content),
+        Err(why) => {
+            println!("Failed loading embed template: {}", why);
+            "Couldn't load the match post fields".to_owned().into()
+        }
+    }
+}
+
+/// Saves a change made with the `/embed` menu or reset button.
+pub async fn update(
+    component: &MessageComponentInteraction,
+    client: &Client,
+    guild_id: GuildId,
+    action: Action,
+) -> Reply {
+    if !commands::is_admin(component.member.as_ref()) {
+        return "Only server admins can change match posts"
+            .to_owned()
+            .into();
+    }
+
+    let result = match action {
+        Action::Select => {
+            let selected: Vec<Element> = component
+                .data
+                .values
+                .iter()
+                .filter_map(|value| Element::from_str(value))
+                .collect();
+            match load(client, guild_id).await {
+                Ok(template) => save(client, guild_id, Some(&template.select(&selected))).await,
+                Err(why) => Err(why),
+            }
+        }
+        Action::Reset => save(client, guild_id, None).await,
+    };
+    if let Err(why) = result {
+        println!("Failed saving embed template: {}", why);
+        return "Couldn't save the match post fields".to_owned().into();
+    }
+
+    match load(client, guild_id).await {
+        Ok(template) => template_reply(&template, "Saved."),
+        Err(why) => {
+            println!("Failed loading embed template: {}", why);
+            "Couldn't load the match post fields".to_owned().into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keeps_order_and_ignores_case_and_spacing() {
+        let template = Template::parse(" Medals, CSR change,kda ,, Map Image").unwrap();
+        assert_eq!(
+            template.elements(),
+            [
+                Element::Medals,
+                Element::CsrChange,
+                Element::Kda,
+                Element::MapImage
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_drops_duplicates() {
+        let template = Template::parse("rank, kda, RANK, kda").unwrap();
+        assert_eq!(template.elements(), [Element::Rank, Element::Kda]);
+    }
+
+    #[test]
+    fn parse_reports_the_first_unknown_name() {
+        assert_eq!(
+            Template::parse("rank, Score, kills"),
+            Err("Score".to_owned())
+        );
+        assert_eq!(Template::parse(""), Ok(Template::new(Vec::new())));
+    }
+
+    #[test]
+    fn select_keeps_existing_order_and_appends_new_elements() {
+        let template = Template::new(vec![Element::Medals, Element::Rank, Element::Kda]);
+        let selected =
+            template.select(&[Element::Emblem, Element::Kda, Element::Map, Element::Medals]);
+        assert_eq!(
+            selected.elements(),
+            [Element::Medals, Element::Kda, Element::Map, Element::Emblem]
+        );
+        assert_eq!(template.select(&[]).elements(), []);
+    }
+}
@@ -0,0 +1,102 @@
+use reqwest::StatusCode;
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, CortanaError>;
+
+#[derive(Debug)]
+pub enum CortanaError {
+    /// The Halo API could not be reached or returned an error status.
+    Api(reqwest::Error),
+    /// A Halo API response or recording didn't match the expected shape.
+    Json(serde_json::Error),
+    /// The Halo API asked for requests to stop for longer than
+    /// `backoff_max_ms`.
+    RateLimited {
+        retry_in: std::time::Duration,
+    },
+    /// A `FixtureHaloApi` has no recording for a request.
+    MissingFixture(String),
+    Database(tokio_postgres::Error),
+    Discord(serenity::Error),
+    /// A ranked match came back without CSR progression for the player.
+    MissingProgression {
+        match_id: String,
+    },
+    /// A match came back without any players.
+    EmptyMatch {
+        match_id: String,
+    },
+    /// A chart couldn't be drawn or encoded.
+    Chart(String),
+}
+
+impl fmt::Display for CortanaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CortanaError::Api(why) => write!(f, "Halo API error: {}", why),
+            CortanaError::Json(why) => write!(f, "unexpected Halo API response: {}", why),
+            CortanaError::RateLimited { retry_in } => write!(
+                f,
+                "rate limited by the Halo API for another {}s",
+                retry_in.as_secs()
+            ),
+            CortanaError::MissingFixture(what) => write!(f, "no recorded response for {}", what),
+            CortanaError::Database(why) => write!(f, "database error: {}", why),
+            CortanaError::Discord(why) => write!(f, "Discord error: {}", why),
+            CortanaError::MissingProgression { match_id } => {
+                write!(f, "match {} has no CSR progression", match_id)
+            }
+            CortanaError::EmptyMatch { match_id } => write!(f, "match {} has no players", match_id),
+            CortanaError::Chart(why) => write!(f, "couldn't draw chart: {}", why),
+        }
+    }
+}
+
+impl CortanaError {
+    /// Whether the Halo API doesn't know the requested player or match.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            CortanaError::Api(why) => why.status().is_some_and(|status| {
+                status == StatusCode::NOT_FOUND || status == StatusCode::BAD_REQUEST
+            }),
+            CortanaError::MissingFixture(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for CortanaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CortanaError::Api(why) => Some(why),
+            CortanaError::Json(why) => Some(why),
+            CortanaError::Database(why) => Some(why),
+            CortanaError::Discord(why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for CortanaError {
+    fn from(why: reqwest::Error) -> Self {
+        CortanaError::Api(why)
+    }
+}
+
+impl From<serde_json::Error> for CortanaError {
+    fn from(why: serde_json::Error) -> Self {
+        CortanaError::Json(why)
+    }
+}
+
+impl From<tokio_postgres::Error> for CortanaError {
+    fn from(why: tokio_postgres::Error) -> Self {
+        CortanaError::Database(why)
+    }
+}
+
+impl From<serenity::Error> for CortanaError {
+    fn from(why: serenity::Error) -> Self {
+        CortanaError::Discord(why)
+    }
+}
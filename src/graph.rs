@@ -0,0 +1,371 @@
+use crate::commands::{self, Reply};
+use crate::config::Config;
+use crate::error::{CortanaError, Result};
+use crate::matches_response::Tier;
+use crate::rank::StoredRank;
+use crate::stats::{self, Period};
+use chrono::{DateTime, Utc};
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use plotters::prelude::*;
+use serenity::builder::CreateEmbed;
+use serenity::http::AttachmentType;
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+};
+use std::borrow::Cow;
+use tokio_postgres::Client;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 400;
+const FILENAME: &str = "csr.png";
+
+/// CSR spanned by each tier below Onyx, and by each of its divisions.
+const TIER_CSR: i32 = 300;
+const DIVISION_CSR: i32 = 50;
+
+/// How much CSR to show above and below the line.
+const PADDING_CSR: i32 = 50;
+
+/// The tiers in CSR order, with the CSR each one starts at.
+const TIERS: [(Tier, i32); 6] = [
+    (Tier::Bronze, 0),
+    (Tier::Silver, TIER_CSR),
+    (Tier::Gold, 2 * TIER_CSR),
+    (Tier::Platinum, 3 * TIER_CSR),
+    (Tier::Diamond, 4 * TIER_CSR),
+    (Tier::Onyx, 5 * TIER_CSR),
+];
+
+fn tier_color(tier: Tier) -> RGBColor {
+    match tier {
+        Tier::Unranked => RGBColor(128, 128, 128),
+        Tier::Bronze => RGBColor(205, 127, 50),
+        Tier::Silver => RGBColor(170, 170, 180),
+        Tier::Gold => RGBColor(255, 200, 0),
+        Tier::Platinum => RGBColor(60, 190, 190),
+        Tier::Diamond => RGBColor(70, 130, 255),
+        Tier::Onyx => RGBColor(40, 40, 40),
+    }
+}
+
+/// The part of a tier that falls within the chart.
+#[derive(Debug, PartialEq)]
+struct TierBand {
+    tier: Tier,
+    low: i32,
+    high: i32,
+    /// CSR of each division boundary inside the band. Onyx has none.
+    divisions: Vec<i32>,
+}
+
+/// The tiers visible between `low` and `high` CSR, lowest first.
+fn tier_bands(low: i32, high: i32) -> Vec<TierBand> {
+    let mut bands = Vec::new();
+    for (i, (tier, start)) in TIERS.iter().enumerate() {
+        let end = TIERS.get(i + 1).map_or(high, |(_, next)| *next);
+        let (band_low, band_high) = (*start.max(&low), end.min(high));
+        if band_low >= band_high {
+            continue;
+        }
+
+        let divisions = if *tier == Tier::Onyx {
+            Vec::new()
+        } else {
+            (band_low..band_high)
+                .filter(|value| value % DIVISION_CSR == 0 && value != start)
+                .collect()
+        };
+        bands.push(TierBand {
+            tier: *tier,
+            low: band_low,
+            high: band_high,
+            divisions,
+        });
+    }
+    bands
+}
+
+fn chart_error(why: impl std::fmt::Display) -> CortanaError {
+    CortanaError::Chart(why.to_string())
+}
+
+/// Draws `csr`, one point per match, over bands colored by tier, and encodes
+/// it as a PNG. The chart has no text so it doesn't depend on system fonts;
+/// the embed it's attached to carries the labels.
+fn render_chart(csr: &[i32]) -> Result<Vec<u8>> {
+    let low = (csr.iter().copied().min().unwrap_or(0) - PADDING_CSR).max(0);
+    let high = csr.iter().copied().max().unwrap_or(0) + PADDING_CSR;
+    let last = csr.len().saturating_sub(1).max(1);
+
+    let mut pixels = vec![0; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut pixels, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE).map_err(chart_error)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .margin(10)
+            .build_cartesian_2d(0..last, low..high)
+            .map_err(chart_error)?;
+
+        for band in tier_bands(low, high) {
+            let color = tier_color(band.tier);
+            chart
+                .draw_series(std::iter::once(Rectangle::new(
+                    [(0, band.low), (last, band.high)],
+                    color.mix(0.3).filled(),
+                )))
+                .map_err(chart_error)?;
+
+            let divisions = band
+                .divisions
+                .iter()
+                .map(|value| PathElement::new(vec![(0, *value), (last, *value)], color.mix(0.6)));
+            chart.draw_series(divisions).map_err(chart_error)?;
+        }
+
+        let points: Vec<(usize, i32)> = csr.iter().copied().enumerate().collect();
+        chart
+            .draw_series(LineSeries::new(
+                points.iter().copied(),
+                BLACK.stroke_width(3),
+            ))
+            .map_err(chart_error)?;
+        chart
+            .draw_series(
+                points
+                    .iter()
+                    .map(|point| Circle::new(*point, 4, BLACK.filled())),
+            )
+            .map_err(chart_error)?;
+
+        root.present().map_err(chart_error)?;
+    }
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(&pixels, WIDTH, HEIGHT, ColorType::Rgb8)
+        .map_err(chart_error)?;
+    Ok(png)
+}
+
+/// A player's CSR history in the playlist of their latest ranked match.
+struct CsrHistory {
+    playlist: String,
+    games: usize,
+    start: StoredRank,
+    end: StoredRank,
+    /// The CSR before the first match, then after each match.
+    csr: Vec<i32>,
+}
+
+async fn csr_history(
+    client: &Client,
+    gamertag: &str,
+    since: Option<DateTime<Utc>>,
+) -> Result<Option<CsrHistory>> {
+    let latest = client
+        .query_opt(
+            "select m.queue, m.input from match_players mp join matches m on m.id = mp.match_id \
+             where mp.gamertag = $1 and m.ranked and mp.post_csr is not null \
+             and ($2::timestamptz is null or m.played_at >= $2) \
+             order by m.played_at desc limit 1",
+            &[&gamertag, &since],
+        )
+        .await?;
+    let (queue, input): (Option<&str>, Option<&str>) = match &latest {
+        Some(row) => (row.get(0), row.get(1)),
+        None => return Ok(None),
+    };
+
+    let rows = client
+        .query(
+            "select mp.pre_csr_tier, mp.pre_csr_sub_tier, mp.pre_csr, \
+             mp.post_csr_tier, mp.post_csr_sub_tier, mp.post_csr \
+             from match_players mp join matches m on m.id = mp.match_id \
+             where mp.gamertag = $1 and m.ranked and mp.post_csr is not null \
+             and ($2::timestamptz is null or m.played_at >= $2) \
+             and m.queue is not distinct from $3 and m.input is not distinct from $4 \
+             order by m.played_at",
+            &[&gamertag, &since, &queue, &input],
+        )
+        .await?;
+
+    let ranks: Vec<(Option<StoredRank>, StoredRank)> = rows
+        .iter()
+        .filter_map(|row| Some((StoredRank::from_row(row, 0), StoredRank::from_row(row, 3)?)))
+        .collect();
+    let (first, last) = match (ranks.first(), ranks.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(None),
+    };
+
+    // Placement matches have no CSR going in, so they start from their
+    // result instead.
+    let mut csr: Vec<i32> = first.0.iter().map(|pre| pre.value).collect();
+    csr.extend(ranks.iter().map(|(_, post)| post.value));
+
+    Ok(Some(CsrHistory {
+        playlist: crate::match_card::ranked_playlist_label(queue, input),
+        games: ranks.len(),
+        start: first.0.unwrap_or(first.1),
+        end: last.1,
+        csr,
+    }))
+}
+
+pub async fn graph_command(
+    command: &ApplicationCommandInteraction,
+    client: &Client,
+    config: &Config,
+) -> Reply {
+    let user = match commands::option(command, "user") {
+        Some(ApplicationCommandInteractionDataOptionValue::User(user, _)) => user,
+        _ => &command.user,
+    };
+    let period = match commands::option(command, "period") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(period)) => {
+            Period::from_option(Some(period))
+        }
+        _ => Period::from_option(None),
+    };
+
+    let gamertag = match commands::gamertag_for(client, user.id).await {
+        Ok(Some(gamertag)) => gamertag,
+        Ok(None) => return format!("{} hasn't registered a gamertag", user.name).into(),
+        Err(why) => {
+            println!("Failed looking up gamertag: {}", why);
+            return "Couldn't look up that player".to_owned().into();
+        }
+    };
+
+    let history = match csr_history(client, &gamertag.to_lowercase(), period.since(config)).await {
+        Ok(Some(history)) => history,
+        Ok(None) => {
+            return format!(
+                "{} has no stored ranked games for {}",
+                gamertag,
+                period.describe()
+            )
+            .into()
+        }
+        Err(why) => {
+            println!("Failed getting CSR history for {}: {}", gamertag, why);
+            return "Couldn't get CSR history right now".to_owned().into();
+        }
+    };
+
+    let png = match render_chart(&history.csr) {
+        Ok(png) => png,
+        Err(why) => {
+            println!("Failed drawing CSR chart for {}: {}", gamertag, why);
+            return "Couldn't draw the chart right now".to_owned().into();
+        }
+    };
+
+    let change = history.end.value - history.start.value;
+    let peak = history
+        .csr
+        .iter()
+        .max()
+        .copied()
+        .unwrap_or(history.end.value);
+    let color = if change >= 0 {
+        (0, 255, 0)
+    } else {
+        (255, 0, 0)
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("{}'s CSR for {}", gamertag, period.describe()))
+        .description(format!(
+            "Ranked {}, {} games",
+            history.playlist, history.games
+        ))
+        .color(color)
+        .field("Start", history.start.label(), true)
+        .field("Now", history.end.label(), true)
+        .field(
+            "Change",
+            format!("{} (peak {})", stats::format_change(change as i64), peak),
+            true,
+        )
+        .image(format!("attachment://{}", FILENAME));
+
+    Reply {
+        embed: Some(embed),
+        files: vec![AttachmentType::Bytes {
+            data: Cow::Owned(png),
+            filename: FILENAME.to_owned(),
+        }],
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tier_bands_clip_to_the_chart_and_mark_divisions() {
+        let bands = tier_bands(520, 1560);
+
+        assert_eq!(
+            bands,
+            vec![
+                TierBand {
+                    tier: Tier::Silver,
+                    low: 520,
+                    high: 600,
+                    divisions: vec![550],
+                },
+                TierBand {
+                    tier: Tier::Gold,
+                    low: 600,
+                    high: 900,
+                    divisions: vec![650, 700, 750, 800, 850],
+                },
+                TierBand {
+                    tier: Tier::Platinum,
+                    low: 900,
+                    high: 1200,
+                    divisions: vec![950, 1000, 1050, 1100, 1150],
+                },
+                TierBand {
+                    tier: Tier::Diamond,
+                    low: 1200,
+                    high: 1500,
+                    divisions: vec![1250, 1300, 1350, 1400, 1450],
+                },
+                TierBand {
+                    tier: Tier::Onyx,
+                    low: 1500,
+                    high: 1560,
+                    divisions: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tier_bands_skip_tiers_outside_the_chart() {
+        let tiers: Vec<Tier> = tier_bands(0, 250)
+            .into_iter()
+            .map(|band| band.tier)
+            .collect();
+        assert_eq!(tiers, vec![Tier::Bronze]);
+    }
+
+    #[test]
+    fn history_is_labelled_by_queue_and_input() {
+        assert_eq!(
+            crate::match_card::ranked_playlist_label(Some("solo-duo"), Some("mnk")),
+            "Solo/Duo M+K"
+        );
+        assert_eq!(
+            crate::match_card::ranked_playlist_label(Some("open"), None),
+            "Open Unknown"
+        );
+    }
+}
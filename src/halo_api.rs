@@ -0,0 +1,369 @@
+use crate::config::HaloConfig;
+use crate::emblem_request::EmblemRequest;
+use crate::emblem_response::EmblemResponse;
+use crate::error::{CortanaError, Result};
+use crate::match_request::MatchRequest;
+use crate::match_response::MatchResponse;
+use crate::matches_request::{Limit, MatchesRequest, Mode};
+use crate::matches_response::MatchesResponse;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serenity::async_trait;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+/// The parts of the Halo Infinite stats API the bot uses.
+#[async_trait]
+pub trait HaloApi: Send + Sync {
+    /// Lists a player's matches of one mode newest first, skipping the
+    /// `offset` most recent ones.
+    async fn get_matches(
+        &self,
+        gamertag: &str,
+        mode: Mode,
+        count: usize,
+        offset: usize,
+    ) -> Result<MatchesResponse>;
+
+    async fn get_match(&self, match_id: &str) -> Result<MatchResponse>;
+
+    async fn get_emblem(&self, gamertag: &str) -> Result<EmblemResponse>;
+}
+
+/// Talks to the real API over a single pooled `reqwest::Client`.
+///
+/// Every request goes through one shared layer that caps how many requests
+/// are in flight at once, pauses every request until `Retry-After` has passed
+/// on a 429 and retries transient failures with exponential backoff and
+/// jitter.
+pub struct HttpHaloApi {
+    client: reqwest::Client,
+    config: HaloConfig,
+    token: String,
+    in_flight: Semaphore,
+    pause: Pause,
+}
+
+impl HttpHaloApi {
+    pub fn new(
+        config: &HaloConfig,
+    ) -> std::result::Result<HttpHaloApi, Box<dyn std::error::Error>> {
+        Ok(HttpHaloApi {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.timeout_secs))
+                .build()?,
+            config: config.clone(),
+            token: std::env::var("HALO_API_TOKEN")?,
+            in_flight: Semaphore::new(config.max_in_flight),
+            pause: Pause::default(),
+        })
+    }
+
+    async fn post<Req, Res>(&self, path: &str, request: &Req) -> Result<Res>
+    where
+        Req: Serialize + ?Sized,
+        Res: DeserializeOwned,
+    {
+        let mut attempt = 0;
+
+        loop {
+            let result = {
+                let _permit = self.in_flight.acquire().await.expect("semaphore closed");
+                self.wait_out_pause().await?;
+                self.client
+                    .post(self.config.endpoint(path))
+                    .bearer_auth(&self.token)
+                    .json(request)
+                    .send()
+                    .await
+            };
+
+            let retry_in = match &result {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    match retry_after(response.headers()) {
+                        Some(delay) => {
+                            self.pause.extend(delay);
+                            if delay > self.max_wait() {
+                                return Err(CortanaError::RateLimited { retry_in: delay });
+                            }
+                            Some(delay)
+                        }
+                        None => Some(backoff(&self.config, attempt)),
+                    }
+                }
+                Ok(response) if response.status().is_server_error() => {
+                    Some(backoff(&self.config, attempt))
+                }
+                Ok(_) => None,
+                Err(why) if why.is_timeout() || why.is_connect() => {
+                    Some(backoff(&self.config, attempt))
+                }
+                Err(_) => None,
+            };
+
+            match retry_in {
+                Some(delay) if attempt < self.config.max_retries => {
+                    println!(
+                        "Retrying {} in {:.1}s (attempt {})",
+                        path,
+                        delay.as_secs_f64(),
+                        attempt + 1
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => return Ok(result?.error_for_status()?.json().await?),
+            }
+        }
+    }
+
+    fn max_wait(&self) -> Duration {
+        Duration::from_millis(self.config.backoff_max_ms)
+    }
+
+    /// Waits until a `Retry-After` another request got has passed, or fails
+    /// straight away if that's longer than `backoff_max_ms` off.
+    async fn wait_out_pause(&self) -> Result<()> {
+        loop {
+            let remaining = self.pause.remaining();
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            if remaining > self.max_wait() {
+                return Err(CortanaError::RateLimited {
+                    retry_in: remaining,
+                });
+            }
+            tokio::time::sleep(remaining).await;
+        }
+    }
+}
+
+/// When requests may be sent again after a 429, shared by every request.
+#[derive(Default)]
+struct Pause {
+    until: Mutex<Option<Instant>>,
+}
+
+impl Pause {
+    /// Holds requests for at least `delay` from now. Never shortens a pause
+    /// that's already longer.
+    fn extend(&self, delay: Duration) {
+        let mut until = self.until.lock().expect("pause lock poisoned");
+        let end = Instant::now() + delay;
+        if until.is_none_or(|until| until < end) {
+            *until = Some(end);
+        }
+    }
+
+    fn remaining(&self) -> Duration {
+        let until = self.until.lock().expect("pause lock poisoned");
+        until.map_or(Duration::ZERO, |until| {
+            until.saturating_duration_since(Instant::now())
+        })
+    }
+}
+
+/// Exponential backoff with full jitter: a random delay between zero and
+/// `backoff_base_ms * 2^attempt`, capped at `backoff_max_ms`.
+fn backoff(config: &HaloConfig, attempt: u32) -> Duration {
+    let ceiling = config
+        .backoff_base_ms
+        .saturating_mul(1 << attempt.min(16))
+        .min(config.backoff_max_ms);
+    Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling))
+}
+
+/// Reads a `Retry-After` header given in seconds. The HTTP-date form isn't
+/// used by the API, so it falls back to regular backoff.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds: u64 = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+#[async_trait]
+impl HaloApi for HttpHaloApi {
+    async fn get_matches(
+        &self,
+        gamertag: &str,
+        mode: Mode,
+        count: usize,
+        offset: usize,
+    ) -> Result<MatchesResponse> {
+        let request = MatchesRequest {
+            gamertag,
+            limit: Limit { count, offset },
+            mode,
+        };
+        self.post("stats/matches/list/", &request).await
+    }
+
+    async fn get_match(&self, match_id: &str) -> Result<MatchResponse> {
+        self.post("stats/matches/retrieve", &MatchRequest { id: match_id })
+            .await
+    }
+
+    async fn get_emblem(&self, gamertag: &str) -> Result<EmblemResponse> {
+        let request = EmblemRequest {
+            gamertag: gamertag.to_owned(),
+        };
+        self.post("appearance", &request).await
+    }
+}
+
+/// Replays recorded API responses instead of calling the API.
+///
+/// Responses are kept as raw JSON and deserialized on every call, so the
+/// same recording can be handed out any number of times. Gamertags are
+/// matched case-insensitively, like the real API.
+#[derive(Default)]
+pub struct FixtureHaloApi {
+    matches: HashMap<String, String>,
+    custom_matches: HashMap<String, String>,
+    match_details: HashMap<String, String>,
+    emblems: HashMap<String, String>,
+}
+
+impl FixtureHaloApi {
+    /// Loads every recording under `dir`, laid out as
+    /// `matches/<gamertag>.json`, `custom/<gamertag>.json`,
+    /// `match/<match id>.json` and `appearance/<gamertag>.json`. Missing
+    /// subdirectories are skipped.
+    pub fn from_dir(dir: &Path) -> std::io::Result<FixtureHaloApi> {
+        Ok(FixtureHaloApi {
+            matches: read_fixtures(&dir.join("matches"))?,
+            custom_matches: read_fixtures(&dir.join("custom"))?,
+            match_details: read_fixtures(&dir.join("match"))?,
+            emblems: read_fixtures(&dir.join("appearance"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+impl FixtureHaloApi {
+    /// Records a player's match list of one mode, for tests that need more
+    /// history than the fixtures have.
+    pub fn record_matches(&mut self, mode: Mode, response: &MatchesResponse) {
+        let json = serde_json::to_string(response).expect("responses serialize");
+        let gamertag = response.additional.gamertag.to_lowercase();
+        match mode {
+            Mode::Matchmade => self.matches.insert(gamertag, json),
+            Mode::Custom => self.custom_matches.insert(gamertag, json),
+        };
+    }
+}
+
+fn read_fixtures(dir: &Path) -> std::io::Result<HashMap<String, String>> {
+    let mut fixtures = HashMap::new();
+    if !dir.is_dir() {
+        return Ok(fixtures);
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+            fixtures.insert(name.to_lowercase(), std::fs::read_to_string(&path)?);
+        }
+    }
+
+    Ok(fixtures)
+}
+
+fn replay<T: DeserializeOwned>(
+    fixtures: &HashMap<String, String>,
+    kind: &str,
+    key: &str,
+) -> Result<T> {
+    let json = fixtures
+        .get(&key.to_lowercase())
+        .ok_or_else(|| CortanaError::MissingFixture(format!("{} {}", kind, key)))?;
+    Ok(serde_json::from_str(json)?)
+}
+
+#[async_trait]
+impl HaloApi for FixtureHaloApi {
+    async fn get_matches(
+        &self,
+        gamertag: &str,
+        mode: Mode,
+        count: usize,
+        offset: usize,
+    ) -> Result<MatchesResponse> {
+        let mut response: MatchesResponse = match mode {
+            Mode::Matchmade => replay(&self.matches, "matches", gamertag)?,
+            Mode::Custom => replay(&self.custom_matches, "custom", gamertag)?,
+        };
+        response.data.drain(..offset.min(response.data.len()));
+        response.data.truncate(count);
+        Ok(response)
+    }
+
+    async fn get_match(&self, match_id: &str) -> Result<MatchResponse> {
+        replay(&self.match_details, "match", match_id)
+    }
+
+    async fn get_emblem(&self, gamertag: &str) -> Result<EmblemResponse> {
+        replay(&self.emblems, "appearance", gamertag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn backoff_stays_under_the_growing_ceiling_and_the_cap() {
+        let config = HaloConfig {
+            backoff_base_ms: 100,
+            backoff_max_ms: 1_000,
+            ..HaloConfig::default()
+        };
+        for _ in 0..100 {
+            assert!(backoff(&config, 0) <= Duration::from_millis(100));
+            assert!(backoff(&config, 2) <= Duration::from_millis(400));
+            assert!(backoff(&config, 10) <= Duration::from_millis(1_000));
+            assert!(backoff(&config, 40) <= Duration::from_millis(1_000));
+        }
+    }
+
+    #[test]
+    fn retry_after_reads_seconds_only() {
+        let header = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert_eq!(retry_after(&header("12")), Some(Duration::from_secs(12)));
+        assert_eq!(retry_after(&header(" 3 ")), Some(Duration::from_secs(3)));
+        assert_eq!(retry_after(&header("Wed, 21 Oct 2015 07:28:00 GMT")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn pauses_only_ever_get_longer() {
+        let pause = Pause::default();
+        assert_eq!(pause.remaining(), Duration::ZERO);
+
+        pause.extend(Duration::from_secs(60));
+        pause.extend(Duration::from_secs(1));
+        assert!(pause.remaining() > Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::error::{CortanaError, Result};
+use crate::match_response::MatchResponse;
+use crate::matches_response::Data;
+use tokio_postgres::Client;
+
+/// Stores a processed match and the registered player's line in it, all or
+/// nothing, so a failure partway through doesn't leave a match missing its
+/// teams, players or medals.
+///
+/// Every insert is idempotent, so storing the same match once per registered
+/// player in it (or again after a restart) is harmless.
+pub async fn store_match(
+    client: &mut Client,
+    gamertag: &str,
+    data: &Data,
+    match_response: &MatchResponse,
+) -> Result<()> {
+    let players = &match_response.data.players;
+    let player_count = players.len().max(1) as f64;
+    let avg_kills = players
+        .iter()
+        .map(|player| player.stats.core.summary.kills as f64)
+        .sum::<f64>()
+        / player_count;
+    let avg_damage = players
+        .iter()
+        .map(|player| player.stats.core.damage.dealt as f64)
+        .sum::<f64>()
+        / player_count;
+
+    let transaction = client.transaction().await?;
+
+    let properties = data
+        .details
+        .playlist
+        .as_ref()
+        .map(|playlist| &playlist.properties);
+    // A ranked row without CSR would throw off the leaderboard and charts.
+    if properties.is_some_and(|properties| properties.ranked) && data.player.progression.is_none() {
+        return Err(CortanaError::MissingProgression {
+            match_id: data.id.clone(),
+        });
+    }
+    transaction
+        .execute(
+            "insert into matches (id, played_at, duration_seconds, category, map_name, \
+             map_thumbnail_url, ranked, queue, input, player_count, avg_kills, avg_damage) \
+             values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) \
+             on conflict (id) do nothing",
+            &[
+                &data.id,
+                &data.played_at,
+                &(data.duration.seconds as i32),
+                &data.details.category.name,
+                &data.details.map.name,
+                &data.details.map.asset.thumbnail_url,
+                &properties.is_some_and(|properties| properties.ranked),
+                &properties
+                    .and_then(|properties| properties.queue.as_ref())
+                    .map(|queue| queue.as_str()),
+                &properties
+                    .and_then(|properties| properties.input.as_ref())
+                    .map(|input| input.as_str()),
+                &(players.len() as i32),
+                &avg_kills,
+                &avg_damage,
+            ],
+        )
+        .await?;
+
+    for team in &match_response.data.teams.details {
+        let summary = &team.stats.core.summary;
+        let damage = &team.stats.core.damage;
+        transaction
+            .execute(
+                "insert into match_teams (match_id, team_id, name, mmr, kills, deaths, assists, \
+                 damage_dealt, damage_taken) values ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+                 on conflict (match_id, team_id) do nothing",
+                &[
+                    &data.id,
+                    &(team.team.id as i32),
+                    &team.team.name,
+                    &team.team.skill.mmr,
+                    &(summary.kills as i32),
+                    &(summary.deaths as i32),
+                    &(summary.assists as i32),
+                    &(damage.dealt as i32),
+                    &(damage.taken as i32),
+                ],
+            )
+            .await?;
+    }
+
+    let gamertag = gamertag.to_lowercase();
+    let player = &data.player;
+    let stats = &player.stats.core;
+    let csr = player
+        .progression
+        .as_ref()
+        .map(|progression| &progression.csr);
+    transaction
+        .execute(
+            "insert into match_players (match_id, gamertag, team_id, rank, outcome, kills, \
+             deaths, assists, kda, damage_dealt, damage_taken, accuracy, pre_csr_tier, \
+             pre_csr_sub_tier, pre_csr, post_csr_tier, post_csr_sub_tier, post_csr) \
+             values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, \
+             $17, $18) on conflict (match_id, gamertag) do nothing",
+            &[
+                &data.id,
+                &gamertag,
+                &(player.team.id as i32),
+                &(player.rank as i32),
+                &player.outcome.as_str(),
+                &(stats.summary.kills as i32),
+                &(stats.summary.deaths as i32),
+                &(stats.summary.assists as i32),
+                &stats.kda,
+                &(stats.damage.dealt as i32),
+                &(stats.damage.taken as i32),
+                &stats.shots.accuracy,
+                &csr.map(|csr| csr.pre_match.tier.as_str()),
+                &csr.map(|csr| csr.pre_match.sub_tier as i32),
+                &csr.map(|csr| csr.pre_match.value as i32),
+                &csr.map(|csr| csr.post_match.tier.as_str()),
+                &csr.map(|csr| csr.post_match.sub_tier as i32),
+                &csr.map(|csr| csr.post_match.value as i32),
+            ],
+        )
+        .await?;
+
+    for medal in &stats.breakdowns.medals {
+        transaction
+            .execute(
+                "insert into match_player_medals (match_id, gamertag, name, count) \
+                 values ($1, $2, $3, $4) on conflict (match_id, gamertag, name) do nothing",
+                &[&data.id, &gamertag, &medal.name, &(medal.count as i32)],
+            )
+            .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
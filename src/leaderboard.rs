@@ -0,0 +1,286 @@
+use crate::commands::{self, Reply};
+use crate::config::Config;
+use crate::error::Result;
+use crate::matches_response::Tier;
+use crate::rank;
+use crate::stats::Period;
+use serenity::builder::{CreateApplicationCommandOption, CreateComponents, CreateEmbed};
+use serenity::model::id::GuildId;
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+    ApplicationCommandOptionType,
+};
+use serenity::model::interactions::message_component::ButtonStyle;
+use tokio_postgres::Client;
+
+const PAGE_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sort {
+    Csr,
+    WinRate,
+    Kda,
+    Damage,
+}
+
+impl Sort {
+    fn from_option(value: Option<&str>) -> Sort {
+        match value {
+            Some("winrate") => Sort::WinRate,
+            Some("kda") => Sort::Kda,
+            Some("damage") => Sort::Damage,
+            _ => Sort::Csr,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Sort::Csr => "csr",
+            Sort::WinRate => "winrate",
+            Sort::Kda => "kda",
+            Sort::Damage => "damage",
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Sort::Csr => "CSR",
+            Sort::WinRate => "win rate",
+            Sort::Kda => "KDA",
+            Sort::Damage => "damage per game",
+        }
+    }
+
+    pub fn create_option(
+        option: &mut CreateApplicationCommandOption,
+    ) -> &mut CreateApplicationCommandOption {
+        option
+            .name("sort")
+            .description("What to rank players by (defaults to current CSR)")
+            .kind(ApplicationCommandOptionType::String)
+            .add_string_choice("CSR", "csr")
+            .add_string_choice("Win rate", "winrate")
+            .add_string_choice("KDA", "kda")
+            .add_string_choice("Damage", "damage")
+    }
+}
+
+struct Entry {
+    gamertag: String,
+    value: String,
+}
+
+/// Players' current CSR comes from their latest stored ranked match, so
+/// players without one are listed last as unranked.
+async fn csr_entries(client: &Client, guild_id: GuildId) -> Result<Vec<Entry>> {
+    let rows = client
+        .query(
+            "select coalesce(u.display_gamertag, u.gamertag), latest.post_csr, latest.post_csr_tier, latest.post_csr_sub_tier \
+             from users u \
+             join guild_users gu on gu.discord_id = u.discord_id \
+             left join lateral ( \
+                 select mp.post_csr, mp.post_csr_tier, mp.post_csr_sub_tier \
+                 from match_players mp join matches m on m.id = mp.match_id \
+                 where mp.gamertag = u.gamertag and m.ranked and mp.post_csr is not null \
+                 order by m.played_at desc limit 1 \
+             ) latest on true \
+             where gu.guild_id = $1 and u.enabled \
+             order by latest.post_csr desc nulls last, u.gamertag",
+            &[&(guild_id.0 as i64)],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let csr: Option<i32> = row.get(1);
+            let tier: Option<&str> = row.get(2);
+            let sub_tier: Option<i32> = row.get(3);
+            let value = match (csr, tier.and_then(|tier| tier.parse::<Tier>().ok())) {
+                (Some(csr), Some(tier)) => format!(
+                    "{} ({})",
+                    rank::rank_label(&tier, sub_tier.unwrap_or(1) as usize),
+                    csr
+                ),
+                _ => format!("{} Unranked", rank::rank_emoji(&Tier::Unranked)),
+            };
+            Entry {
+                gamertag: row.get(0),
+                value,
+            }
+        })
+        .collect())
+}
+
+async fn stat_entries(
+    client: &Client,
+    guild_id: GuildId,
+    sort: Sort,
+    period: Period,
+    config: &Config,
+) -> Result<Vec<Entry>> {
+    let order = match sort {
+        Sort::WinRate => "win_rate",
+        Sort::Kda => "kda",
+        _ => "damage",
+    };
+
+    let rows = client
+        .query(
+            format!(
+                "select coalesce(u.display_gamertag, u.gamertag), count(*) as games, \
+                 100.0 * count(*) filter (where mp.outcome = 'win') / count(*)::float8 as win_rate, \
+                 avg(mp.kda) as kda, avg(mp.damage_dealt)::float8 as damage \
+                 from users u \
+                 join guild_users gu on gu.discord_id = u.discord_id \
+                 join match_players mp on mp.gamertag = u.gamertag \
+                 join matches m on m.id = mp.match_id \
+                 where gu.guild_id = $1 and u.enabled \
+                 and ($2::timestamptz is null or m.played_at >= $2) \
+                 group by u.gamertag, u.display_gamertag \
+                 order by {} desc, u.gamertag",
+                order
+            )
+            .as_str(),
+            &[&(guild_id.0 as i64), &period.since(config)],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let games: i64 = row.get(1);
+            let value = match sort {
+                Sort::WinRate => format!("{:.0}%", row.get::<_, f64>(2)),
+                Sort::Kda => format!("{:.1}", row.get::<_, f64>(3)),
+                _ => format!("{:.0}", row.get::<_, f64>(4)),
+            };
+            Entry {
+                gamertag: row.get(0),
+                value: format!("{} ({} games)", value, games),
+            }
+        })
+        .collect())
+}
+
+/// Renders one page of the leaderboard. The sort, period and page are kept
+/// in the buttons' custom IDs so paging needs no other state.
+pub async fn leaderboard(
+    client: &Client,
+    config: &Config,
+    guild_id: GuildId,
+    sort: Sort,
+    period: Period,
+    page: usize,
+) -> Reply {
+    let entries = match sort {
+        Sort::Csr => csr_entries(client, guild_id).await,
+        _ => stat_entries(client, guild_id, sort, period, config).await,
+    };
+    let entries = match entries {
+        Ok(entries) if entries.is_empty() => {
+            return "Nobody on this server has any games to rank yet"
+                .to_owned()
+                .into()
+        }
+        Ok(entries) => entries,
+        Err(why) => {
+            println!("Failed building leaderboard: {}", why);
+            return "Couldn't build the leaderboard right now".to_owned().into();
+        }
+    };
+
+    let pages = entries.len().div_ceil(PAGE_SIZE);
+    let page = page.min(pages - 1);
+
+    let lines: Vec<String> = entries
+        .iter()
+        .enumerate()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .map(|(i, entry)| format!("**{}.** {} — {}", i + 1, entry.gamertag, entry.value))
+        .collect();
+
+    let title = match sort {
+        Sort::Csr => "Leaderboard by CSR".to_owned(),
+        _ => format!(
+            "Leaderboard by {} for {}",
+            sort.describe(),
+            period.describe()
+        ),
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(title)
+        .color((0, 0, 255))
+        .description(lines.join("\n"))
+        .footer(|footer| footer.text(format!("Page {} of {}", page + 1, pages)));
+
+    let mut components = CreateComponents::default();
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button
+                .style(ButtonStyle::Secondary)
+                .label("◀ Prev")
+                .custom_id(custom_id(sort, period, page.saturating_sub(1)))
+                .disabled(page == 0)
+        })
+        .create_button(|button| {
+            button
+                .style(ButtonStyle::Secondary)
+                .label("Next ▶")
+                .custom_id(custom_id(sort, period, page + 1))
+                .disabled(page + 1 >= pages)
+        })
+    });
+
+    Reply {
+        embed: Some(embed),
+        components: Some(components),
+        ..Default::default()
+    }
+}
+
+fn custom_id(sort: Sort, period: Period, page: usize) -> String {
+    format!("leaderboard:{}:{}:{}", sort.as_str(), period.as_str(), page)
+}
+
+/// Parses a page button's custom ID back into the sort, period and page.
+pub fn parse_custom_id(custom_id: &str) -> Option<(Sort, Period, usize)> {
+    let mut parts = custom_id.strip_prefix("leaderboard:")?.split(':');
+    let sort = Sort::from_option(parts.next());
+    let period = Period::from_option(parts.next());
+    let page = parts.next()?.parse().ok()?;
+    Some((sort, period, page))
+}
+
+pub async fn leaderboard_command(
+    command: &ApplicationCommandInteraction,
+    client: &Client,
+    config: &Config,
+) -> Reply {
+    let guild_id = match command.guild_id {
+        Some(guild_id) => guild_id,
+        None => {
+            return "This command can only be used in a server"
+                .to_owned()
+                .into()
+        }
+    };
+
+    let sort = match commands::option(command, "sort") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(sort)) => {
+            Sort::from_option(Some(sort))
+        }
+        _ => Sort::from_option(None),
+    };
+    let period = match commands::option(command, "period") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(period)) => {
+            Period::from_option(Some(period))
+        }
+        _ => Period::from_option(None),
+    };
+
+    leaderboard(client, config, guild_id, sort, period, 0).await
+}
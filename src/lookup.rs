@@ -0,0 +1,180 @@
+use crate::commands::{self, Reply};
+use crate::embed_template::{self, Template};
+use crate::error::Result;
+use crate::halo_api::HaloApi;
+use crate::match_checker::PAGE_SIZE;
+use crate::matches_request::Mode;
+use crate::matches_response::Data;
+use crate::scoreboard;
+use serenity::builder::CreateComponents;
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+};
+use tokio_postgres::Client;
+
+/// How far back in a player's matchmade and custom history to look for a
+/// match. `/match` can't show anything older, since a match fetched by ID
+/// comes without its map, playlist or when it was played.
+pub const SEARCH_DEPTH: usize = 100;
+
+/// The `gamertag` option, or the registered gamertag of whoever used the
+/// command.
+async fn gamertag_option(
+    command: &ApplicationCommandInteraction,
+    client: &Client,
+) -> std::result::Result<String, Reply> {
+    if let Some(ApplicationCommandInteractionDataOptionValue::String(gamertag)) =
+        commands::option(command, "gamertag")
+    {
+        return Ok(gamertag.clone());
+    }
+
+    match commands::gamertag_for(client, command.user.id).await {
+        Ok(Some(gamertag)) => Ok(gamertag),
+        Ok(None) => Err("Give a gamertag, or register yours first".to_owned().into()),
+        Err(why) => {
+            println!("Failed looking up gamertag: {}", why);
+            Err("Couldn't look up your gamertag".to_owned().into())
+        }
+    }
+}
+
+/// The match with `match_id` from a player's recent history, along with
+/// their gamertag as the Halo API spells it.
+async fn find_match(
+    api: &dyn HaloApi,
+    gamertag: &str,
+    match_id: &str,
+) -> Result<Option<(String, Data)>> {
+    for mode in [Mode::Matchmade, Mode::Custom] {
+        let mut offset = 0;
+        while offset < SEARCH_DEPTH {
+            let page = api.get_matches(gamertag, mode, PAGE_SIZE, offset).await?;
+            let page_len = page.data.len();
+            let gamertag = page.additional.gamertag;
+            if let Some(data) = page.data.into_iter().find(|data| data.id == match_id) {
+                return Ok(Some((gamertag, data)));
+            }
+            if page_len < PAGE_SIZE {
+                break;
+            }
+            offset += page_len;
+        }
+    }
+    Ok(None)
+}
+
+/// A player's most recent match, matchmade or custom.
+async fn latest_match(api: &dyn HaloApi, gamertag: &str) -> Result<Option<(String, Data)>> {
+    let matchmade = api.get_matches(gamertag, Mode::Matchmade, 1, 0).await?;
+    let custom = api.get_matches(gamertag, Mode::Custom, 1, 0).await?;
+    let gamertag = matchmade.additional.gamertag;
+    let latest = matchmade
+        .data
+        .into_iter()
+        .chain(custom.data)
+        .max_by_key(|data| data.played_at);
+    Ok(latest.map(|data| (gamertag, data)))
+}
+
+/// Renders a match the way the poller posts it in this server, with its
+/// scoreboard button.
+async fn render(
+    command: &ApplicationCommandInteraction,
+    client: &Client,
+    api: &dyn HaloApi,
+    gamertag: &str,
+    data: &Data,
+) -> Reply {
+    let template = match command.guild_id {
+        Some(guild_id) => embed_template::load(client, guild_id)
+            .await
+            .unwrap_or_else(|why| {
+                println!("Failed loading embed template for {}: {}", guild_id, why);
+                Template::default()
+            }),
+        None => Template::default(),
+    };
+    let result = match api.get_match(&data.id).await {
+        Ok(match_response) => {
+            crate::match_results_embed(api, data, gamertag, &match_response, &template).await
+        }
+        Err(why) => Err(why),
+    };
+    let embed = match result {
+        Ok(embed) => embed,
+        Err(why) => {
+            println!("Failed rendering match {}: {}", data.id, why);
+            return "Couldn't get that match right now".to_owned().into();
+        }
+    };
+
+    let mut components = CreateComponents::default();
+    scoreboard::add_button(&mut components, &data.id);
+    Reply {
+        embed: Some(embed),
+        components: Some(components),
+        ..Default::default()
+    }
+}
+
+pub async fn match_command(
+    command: &ApplicationCommandInteraction,
+    client: &Client,
+    api: &dyn HaloApi,
+) -> Reply {
+    let match_id = match commands::option(command, "id") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(match_id)) => match_id.trim(),
+        _ => unreachable!("Command type"),
+    };
+    let gamertag = match gamertag_option(command, client).await {
+        Ok(gamertag) => gamertag,
+        Err(reply) => return reply,
+    };
+
+    match find_match(api, &gamertag, match_id).await {
+        Ok(Some((gamertag, data))) => render(command, client, api, &gamertag, &data).await,
+        Ok(None) => format!(
+            "Couldn't find match {} in {}'s last {} games",
+            match_id, gamertag, SEARCH_DEPTH
+        )
+        .into(),
+        Err(why) if why.is_not_found() => {
+            format!("Couldn't find a Halo Infinite player called {}", gamertag).into()
+        }
+        Err(why) => {
+            println!(
+                "Failed finding match {} for {}: {}",
+                match_id, gamertag, why
+            );
+            "Couldn't reach the Halo API, try again later"
+                .to_owned()
+                .into()
+        }
+    }
+}
+
+pub async fn last_command(
+    command: &ApplicationCommandInteraction,
+    client: &Client,
+    api: &dyn HaloApi,
+) -> Reply {
+    let gamertag = match gamertag_option(command, client).await {
+        Ok(gamertag) => gamertag,
+        Err(reply) => return reply,
+    };
+
+    match latest_match(api, &gamertag).await {
+        Ok(Some((gamertag, data))) => render(command, client, api, &gamertag, &data).await,
+        Ok(None) => format!("{} hasn't played any games", gamertag).into(),
+        Err(why) if why.is_not_found() => {
+            format!("Couldn't find a Halo Infinite player called {}", gamertag).into()
+        }
+        Err(why) => {
+            println!("Failed getting latest match for {}: {}", gamertag, why);
+            "Couldn't reach the Halo API, try again later"
+                .to_owned()
+                .into()
+        }
+    }
+}
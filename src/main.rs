@@ -1,120 +1,70 @@
+mod commands;
+mod compare;
+mod config;
+mod embed_template;
 mod emblem_request;
 mod emblem_response;
+mod error;
+mod graph;
+mod halo_api;
+mod history;
+mod leaderboard;
+mod lookup;
+mod match_card;
 mod match_checker;
+mod match_queue;
 mod match_request;
 mod match_response;
 mod matches_request;
 mod matches_response;
-
-use crate::emblem_request::EmblemRequest;
-use crate::emblem_response::EmblemResponse;
-use crate::match_checker::check_for_new_matches;
-use crate::match_request::MatchRequest;
+mod migrations;
+mod rank;
+mod rank_change;
+mod recap;
+mod role_sync;
+mod scoreboard;
+mod sessions;
+mod settings;
+mod stats;
+mod unregister;
+
+use crate::config::{CommandScope, Config};
+use crate::embed_template::Template;
+use crate::error::CortanaError;
+use crate::halo_api::{FixtureHaloApi, HaloApi, HttpHaloApi};
+use crate::match_card::{MatchCard, MatchContext};
+use crate::match_queue::QueuedMatch;
 use crate::match_response::MatchResponse;
-use crate::matches_response::Input::*;
-use crate::matches_response::Outcome;
-use crate::matches_response::Queue::*;
-use crate::matches_response::Tier::*;
-use futures::StreamExt;
+use crate::rank_change::RankChange;
 use matches_response::Data;
 use openssl::ssl::{SslConnector, SslMethod};
 use postgres_openssl::MakeTlsConnector;
-use serenity::model::id::{GuildId, UserId};
 use serenity::{
     async_trait,
+    builder::CreateEmbed,
     http::Http,
     model::{
         channel::Message,
         gateway::Ready,
-        id::ChannelId,
+        guild::Guild,
+        id::{ChannelId, GuildId, UserId},
         interactions::{
             application_command::{
-                ApplicationCommandInteractionDataOptionValue, ApplicationCommandOptionType,
+                ApplicationCommand, ApplicationCommandInteractionDataOptionValue,
             },
-            Interaction, InteractionResponseType,
+            Interaction, InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
         },
     },
     prelude::*,
 };
 use std::error::Error;
 use std::{env, sync::Arc};
+use tokio::time::{self, MissedTickBehavior};
 
 struct Handler {
     client: Arc<tokio_postgres::Client>,
-}
-
-static EMOJIS: [&str; 59] = [
-    "<:AchillesSpine:932071031106072607>",
-    "<:AlwaysRotating:939744585675067393>",
-    "<:BackSmack:932071030825058384>",
-    "<:BankShot:938209152675774475>",
-    "<:Bodyguard:938209152726085712>",
-    "<:Bomber:938136842274996225>",
-    "<:Boogeyman:931631927486734417>",
-    "<:BoomBlock:932071031072518144>",
-    "<:Boxer:932071030766333982>",
-    "<:ClockStop:940066017982427146>",
-    "<:ClusterLuck:940090243145682954>",
-    "<:Demon:931631928120066088>",
-    "<:DoubleKill:931631928023597066>",
-    "<:Extermination:931631929239949343>",
-    "<:Fastball:932071030950867057>",
-    "<:FlagJoust:939786696910860341>",
-    "<:FromtheGrave:932071030795677717>",
-    "<:Fumble:932071030762119168>",
-    "<:GoalLineStand:939786660990836816>",
-    "<:GrappleJack:931631927788728342>",
-    "<:Grenadier:938136887430893668>",
-    "<:GrimReaper:931631928136826900>",
-    "<:GuardianAngel:932071031152214016>",
-    "<:HailMary:938209152105320510>",
-    "<:HoldThis:938209152327647272>",
-    "<:KillingFrenzy:931631928497541171>",
-    "<:KillingSpree:931631928476598302>",
-    "<:Killionaire:931631929311244370>",
-    "<:Killjoy:931631928585642055>",
-    "<:Killtastrophe:931631929667780608>",
-    "<:Killtrocity:931631929642590228>",
-    "<:LastShot:932071030862790706>",
-    "<:Marksman:932071031047340073>",
-    "<:MindtheGap:938426546157412422>",
-    "<:NadeShot:939786758177058837>",
-    "<:Nightmare:931631929067986944>",
-    "<:Ninja:931631929697136770>",
-    "<:NoScope:931631929139277874>",
-    "<:OfftheRack:938136887229562971>",
-    "<:Overkill:931631929617448970>",
-    "<:Pancake:940376937065480263>",
-    "<:Perfect:932071031181570078>",
-    "<:Perfection:931631929646796870>",
-    "<:Quigley:931631929663569980>",
-    "<:Rampage:931631929420296202>",
-    "<:Reversal:932071031101853736>",
-    "<:RunningRiot:931631929621622875>",
-    "<:Scattergunner:938136887288287303>",
-    "<:ShotCaller:938813060506849390>",
-    "<:SneakKing:939503925302820935>",
-    "<:Snipe:931631929575473192>",
-    "<:Steaktacular:938136887611228240>",
-    "<:Stick:937788737583202406>",
-    "<:StoppedShort:938677766587572274>",
-    "<:StraightBalling:938426609864679424>",
-    "<:TripleKill:931631929185411124>",
-    "<:Whiplash:938813020107329606>",
-    "<:Wingman:932071030732767283>",
-    "<:YardSale:932071031068307456>",
-];
-
-fn name_to_emoji(name: &str) -> Option<&str> {
-    let name: String = name.chars().filter(|c| !c.is_whitespace()).collect();
-    let result = EMOJIS
-        .iter()
-        .find(|emoji| emoji.contains(&format!(":{}:", name)))
-        .copied();
-    if let None = result {
-        println!("No emoji for medal: {}", name);
-    }
-    result
+    config: Arc<Config>,
+    api: Arc<dyn HaloApi>,
 }
 
 #[async_trait]
@@ -125,275 +75,243 @@ impl EventHandler for Handler {
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
         if let Interaction::ApplicationCommand(command) = interaction {
-            let content = match command.data.name.as_str() {
-                "register" => {
-                    let options = command
-                        .data
-                        .options
-                        .get(0)
-                        .expect("Expected a gamertag option")
-                        .resolved
-                        .as_ref()
-                        .expect("Expected a gamertag");
-
-                    match options {
-                        ApplicationCommandInteractionDataOptionValue::String(gamertag) => {
-                            register_gamertag(gamertag, command.user.id, &self.client).await
-                        }
-                        _ => unreachable!("Command type"),
+            let deferred = commands::is_deferred(&command.data.name);
+            if deferred {
+                if let Err(why) = command
+                    .create_interaction_response(&ctx.http, |response| {
+                        response.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    })
+                    .await
+                {
+                    println!("Cannot defer slash command: {}", why);
+                    return;
+                }
+            }
+
+            let reply: commands::Reply = match command.data.name.as_str() {
+                "register" => match commands::option(&command, "gamertag") {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(gamertag)) => {
+                        commands::register_gamertag(
+                            gamertag,
+                            command.user.id,
+                            command.guild_id,
+                            &self.client,
+                            self.api.as_ref(),
+                        )
+                        .await
                     }
+                    _ => unreachable!("Command type"),
+                },
+                "unregister" => unregister::unregister_command(&command, &self.client).await,
+                "settings" => settings::settings_command(command.user.id, &self.client).await,
+                "toggle" => commands::toggle_user(command.user.id, &self.client)
+                    .await
+                    .into(),
+                "rankrole" => role_sync::rankrole_command(&command, &ctx.http, &self.client).await,
+                "setchannel" => commands::set_channel(&command, &self.client).await.into(),
+                "stats" => stats::stats_command(&command, &self.client, &self.config).await,
+                "compare" => compare::compare_command(&command, &self.client, &self.config).await,
+                "graph" => graph::graph_command(&command, &self.client, &self.config).await,
+                "match" => lookup::match_command(&command, &self.client, self.api.as_ref()).await,
+                "last" => lookup::last_command(&command, &self.client, self.api.as_ref()).await,
+                "embed" => embed_template::embed_command(&command, &self.client).await,
+                "leaderboard" => {
+                    leaderboard::leaderboard_command(&command, &self.client, &self.config).await
+                }
+                name => {
+                    println!("Unknown command: {}", name);
+                    "That command isn't supported anymore".to_owned().into()
                 }
-                "toggle" => toggle_user(command.user.id, &self.client).await,
-                _ => unreachable!("Unknown command"),
             };
 
-            if let Err(why) = command
+            let result = if deferred {
+                command
+                    .create_followup_message(&ctx.http, |message| reply.apply_followup(message))
+                    .await
+                    .map(|_| ())
+            } else {
+                command
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|message| reply.apply(message))
+                    })
+                    .await
+            };
+            if let Err(why) = result {
+                println!("Cannot respond to slash command: {}", why);
+            }
+        } else if let Interaction::MessageComponent(component) = interaction {
+            let custom_id = component.data.custom_id.as_str();
+
+            // The scoreboard is a new message rather than an update, and the
+            // Halo API can take longer than Discord waits, so it's deferred.
+            if let Some(match_id) = scoreboard::parse_custom_id(custom_id) {
+                if let Err(why) = component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                            .interaction_response_data(|message| {
+                                message.flags(
+                                    InteractionApplicationCommandCallbackDataFlags::EPHEMERAL,
+                                )
+                            })
+                    })
+                    .await
+                {
+                    println!("Cannot defer component: {}", why);
+                    return;
+                }
+
+                let reply = scoreboard::scoreboard(self.api.as_ref(), match_id).await;
+                if let Err(why) = component
+                    .create_followup_message(&ctx.http, |message| reply.apply_followup(message))
+                    .await
+                {
+                    println!("Cannot respond to component: {}", why);
+                }
+                return;
+            }
+
+            let reply = match (
+                leaderboard::parse_custom_id(custom_id),
+                unregister::parse_custom_id(custom_id),
+                settings::parse_custom_id(custom_id),
+                embed_template::parse_custom_id(custom_id),
+                component.guild_id,
+            ) {
+                (Some((sort, period, page)), _, _, _, Some(guild_id)) => {
+                    leaderboard::leaderboard(
+                        &self.client,
+                        &self.config,
+                        guild_id,
+                        sort,
+                        period,
+                        page,
+                    )
+                    .await
+                }
+                (_, Some((confirmed, user_id)), _, _, _) => {
+                    unregister::confirm(&component, &ctx.http, &self.client, confirmed, user_id)
+                        .await
+                }
+                (_, _, Some(setting), _, _) => {
+                    settings::update(&component, &self.client, setting).await
+                }
+                (_, _, _, Some(action), Some(guild_id)) => {
+                    embed_template::update(&component, &self.client, guild_id, action).await
+                }
+                _ => {
+                    println!("Unknown component: {}", component.data.custom_id);
+                    return;
+                }
+            };
+
+            if let Err(why) = component
                 .create_interaction_response(&ctx.http, |response| {
                     response
-                        .kind(InteractionResponseType::ChannelMessageWithSource)
-                        .interaction_response_data(|message| message.content(content))
+                        .kind(InteractionResponseType::UpdateMessage)
+                        .interaction_response_data(|message| reply.apply(message))
                 })
                 .await
             {
-                println!("Cannot respond to slash command: {}", why);
+                println!("Cannot respond to component: {}", why);
             }
         }
     }
 
+    async fn guild_create(&self, ctx: Context, guild: Guild) {
+        if self.config.discord.command_scope != CommandScope::Guild {
+            return;
+        }
+
+        let commands = guild
+            .id
+            .set_application_commands(&ctx.http, commands::create_commands)
+            .await;
+
+        if let Err(why) = commands {
+            println!("Error creating commands for guild {}: {}", guild.id, why);
+        }
+    }
+
     async fn ready(&self, ctx: Context, ready: Ready) {
-        let guild_id = GuildId(460204093722591232);
-
-        let commands = GuildId::set_application_commands(&guild_id, &ctx.http, |commands| {
-            commands
-                .create_application_command(|command| {
-                    command
-                        .name("register")
-                        .description("Register yourself for match updates")
-                        .create_option(|option| {
-                            option
-                                .name("gamertag")
-                                .description("Your GamerTag")
-                                .kind(ApplicationCommandOptionType::String)
-                                .required(true)
-                        })
-                })
-                .create_application_command(|command| {
-                    command
-                        .name("toggle")
-                        .description("Toggle whether your games are displayed")
+        if self.config.discord.command_scope == CommandScope::Global {
+            let commands =
+                ApplicationCommand::set_global_application_commands(&ctx.http, |commands| {
+                    commands::create_commands(commands)
                 })
-        })
-        .await;
+                .await;
 
-        if let Err(why) = commands {
-            println!("Error creating commands: {}", why);
+            if let Err(why) = commands {
+                println!("Error creating commands: {}", why);
+            }
         }
 
         println!("{} is connected!", ready.user.name);
     }
 }
 
-async fn register_gamertag(
+/// One player's result in a match, as posted by the poller and shown by
+/// `/match` and `/last`.
+async fn match_results_embed(
+    api: &dyn HaloApi,
+    data: &Data,
     gamertag: &str,
-    user_id: UserId,
-    client: &tokio_postgres::Client,
-) -> String {
-    let user_id = user_id.0 as i64;
-    let result = client
-        .execute(
-            "insert into users (discord_id, gamertag) values ($1, $2) on conflict (discord_id) do update set gamertag = EXCLUDED.gamertag",
-            &[&user_id, &gamertag.to_lowercase()],
-        )
-        .await;
-    match result {
-        Ok(_) => format!("Registered {}", gamertag),
-        Err(_) => format!("Someone has already registered as {}", gamertag),
-    }
+    match_response: &MatchResponse,
+    template: &Template,
+) -> error::Result<CreateEmbed> {
+    let emblem_url = match api.get_emblem(gamertag).await {
+        Ok(emblem) => Some(emblem.data.emblem_url),
+        Err(why) => {
+            println!("Failed getting emblem for {}: {}", gamertag, why);
+            None
+        }
+    };
+
+    let context = MatchContext::new(gamertag, data, match_response, emblem_url)?;
+    let card = match_card::render_match_embed(data, &context, template);
+    log_unknown_medals(&card);
+    Ok(card.to_embed())
 }
 
-async fn toggle_user(user_id: UserId, client: &tokio_postgres::Client) -> String {
-    let user_id = user_id.0 as i64;
-    let result = client
-        .query(
-            "update users set enabled = not enabled where discord_id = $1 returning enabled",
-            &[&user_id],
-        )
-        .await;
-    let toggle: bool = result.unwrap().first().unwrap().get(0);
-    if toggle {
-        "Your matches will now be shown again".to_owned()
-    } else {
-        "You will no longer see your matches".to_owned()
+/// Medals without an emoji are left out of posts, so they're logged to be
+/// added to `EMOJIS`.
+fn log_unknown_medals(card: &MatchCard) {
+    for name in &card.unknown_medals {
+        println!("No emoji for medal: {}", name);
     }
 }
 
 async fn send_match_results(
+    api: &dyn HaloApi,
     http: &Arc<Http>,
     channel_id: ChannelId,
     data: &Data,
     gamertag: &str,
-    projected_to_win: bool,
-    avg_damage: usize,
-    avg_kpm: f64,
-) -> Result<Message, Box<dyn Error>> {
-    let outcome = &data.player.outcome;
-    let timestamp = &data.played_at;
-
-    let (result, color) = match outcome {
-        Outcome::Win => ("WON", (0, 255, 0)),
-        Outcome::Loss => ("LOST", (255, 0, 0)),
-        Outcome::Draw => ("TIED", (0, 0, 255)),
-        Outcome::Left => ("LEFT", (0, 0, 255)),
-    };
-
-    let stats = &data.player.stats.core;
-
-    let emblem_url = get_emblem(&gamertag).await.expect("emblem").data.emblem_url;
-
-    let medals = &data.player.stats.core.breakdowns.medals;
-    let medal_string: String = medals
-        .iter()
-        .filter_map(|m| name_to_emoji(m.name.as_str()).map(|a| format!("{}x{}", a, m.count)))
-        .reduce(|mut acc, a| {
-            acc.push_str(" ");
-            acc.push_str(a.as_str());
-            acc
-        })
-        .unwrap_or("Nothing special 😔".to_owned());
-
-    let csr = &data.player.progression.as_ref().expect("progression").csr;
-    let csr_change = csr.post_match.value - csr.pre_match.value;
-    let csr_change = if csr_change > 0 {
-        format!("+{}", csr_change)
-    } else {
-        csr_change.to_string()
-    };
-
-    let tier = csr.post_match.sub_tier - 1;
-
-    let rank = match csr.post_match.tier {
-        Unranked => format!("<:Unranked_Rank_Icon:938636507751800892> Unranked {}", tier),
-        Bronze => format!("<:Bronze_Rank_Icon:933098600471363624> Bronze {}", tier),
-        Silver => format!("<:Silver_Rank_Icon:933098600609775646> Silver {}", tier),
-        Gold => format!("<:Gold_Rank_Icon:933098600437776465> Gold {}", tier),
-        Platinum => format!("<:Platinum_Rank_Icon:933098600718802954> Platinum {}", tier),
-        Diamond => format!("<:Diamond_Rank_Icon:933098600488116294> Diamond {}", tier),
-        Onyx => "<:Onyx_Rank_Icon:933098600332931143> Onyx".to_owned(),
-    };
-
-    let input = match data.details.playlist.properties.input {
-        Some(Mnk) => "M+K",
-        Some(Controller) => "Controller",
-        Some(Crossplay) => "Crossplay",
-        None => "Unknown",
-    };
-
-    let queue = match data.details.playlist.properties.queue {
-        Some(SoloDuo) => "Solo/Duo",
-        Some(Open) => "Open",
-        None => "Unknown",
-    };
-
-    let playlist = format!("{} {}", queue, input);
-
-    let projected_to_win = if projected_to_win { "Yes" } else { "No" };
-
-    let mut duration = data.duration.seconds;
-    if duration == 0 {
-        duration = 1
-    };
-    let kpm = data.player.stats.core.summary.kills as f64 / (duration as f64 / 60.0);
+    match_response: &MatchResponse,
+    template: &Template,
+) -> error::Result<Message> {
+    let embed = match_results_embed(api, data, gamertag, match_response, template).await?;
 
     let message = channel_id
         .send_message(http, |m| {
-            m.embed(|e| {
-                e.title(format!(
-                    "{} {} a game of {}!",
-                    gamertag, result, data.details.category.name
-                ))
-                .color(color)
-                .field("Playlist", playlist, true)
-                .field("Rank", format!("{} ({})", rank, csr.post_match.value), true)
-                .field(
-                    "KDA",
-                    format!(
-                        "{}/{}/{} ({})",
-                        stats.summary.kills, stats.summary.deaths, stats.summary.assists, stats.kda
-                    ),
-                    true,
-                )
-                .field("Projected to Win?", projected_to_win, true)
-                .field("CSR change", csr_change, true)
-                .field("KPM / Avg", format!("{:.1} / {:.1}", kpm, avg_kpm), true)
-                .field(
-                    "Accuracy",
-                    format!("{}%", stats.shots.accuracy.round()),
-                    true,
-                )
-                // TODO: Also show average team damage
-                .field(
-                    "Damage Dealt / Avg",
-                    format!("{} / {}", stats.damage.dealt, avg_damage),
-                    true,
-                )
-                .field("Medals", medal_string, true)
-                .image(&data.details.map.asset.thumbnail_url)
-                .url(format!(
-                    "https://halotracker.com/halo-infinite/match/{}",
-                    data.id
-                ))
-                .thumbnail(emblem_url)
-                .timestamp(timestamp)
-            })
+            m.set_embed(embed)
+                .components(|c| scoreboard::add_button(c, &data.id))
         })
         .await?;
 
     Ok(message)
 }
 
-async fn get_emblem(gamertag: &str) -> Result<EmblemResponse, Box<dyn Error>> {
-    let request = EmblemRequest {
-        gamertag: gamertag.to_owned(),
-    };
-
-    let token = std::env::var("HALO_API_TOKEN")?;
-
-    let response = reqwest::Client::new()
-        .post("https://halo.api.stdlib.com/infinite@0.3.8/appearance")
-        .bearer_auth(token)
-        .json(&request)
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    Ok(response)
-}
-
-async fn get_match(match_id: &str) -> Result<MatchResponse, Box<dyn Error>> {
-    let request = MatchRequest { id: match_id };
-    let token = std::env::var("HALO_API_TOKEN")?;
-
-    let response = reqwest::Client::new()
-        .post("https://halo.api.stdlib.com/infinite@0.3.8/stats/matches/retrieve")
-        .bearer_auth(token)
-        .json(&request)
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    Ok(response)
-}
-
-async fn connect_to_db() -> Result<tokio_postgres::Client, Box<dyn Error>> {
+async fn connect_to_db(config: &Config) -> Result<tokio_postgres::Client, Box<dyn Error>> {
     let connection_string = env::var("DB_CONNECTION_STRING")?;
 
     let mut builder = SslConnector::builder(SslMethod::tls())?;
-    builder.set_ca_file("ca-certificate.crt")?;
+    builder.set_ca_file(&config.database.ca_file)?;
     let connector = MakeTlsConnector::new(builder.build());
 
-    let (client, connection) = tokio_postgres::connect(&connection_string, connector).await?;
+    let (mut client, connection) = tokio_postgres::connect(&connection_string, connector).await?;
 
     tokio::spawn(async move {
         if let Err(e) = connection.await {
@@ -401,90 +319,387 @@ async fn connect_to_db() -> Result<tokio_postgres::Client, Box<dyn Error>> {
         }
     });
 
+    migrations::run(&mut client).await?;
+
     Ok(client)
 }
 
-async fn send_matches(client: Arc<tokio_postgres::Client>, http: Arc<Http>) {
-    let new_matches = check_for_new_matches(&client);
+/// Routes every registered player to the configured legacy server and
+/// channel, for installs that predate per-server routing. Only runs while no
+/// server has been set up, so it never overrides one.
+async fn backfill_legacy_guild(
+    client: &tokio_postgres::Client,
+    config: &Config,
+) -> error::Result<()> {
+    let (guild_id, channel_id) = match (
+        config.discord.legacy_guild_id,
+        config.discord.legacy_channel_id,
+    ) {
+        (Some(guild_id), Some(channel_id)) => (guild_id as i64, channel_id as i64),
+        _ => return Ok(()),
+    };
+
+    let inserted = client
+        .execute(
+            "insert into guilds (guild_id, channel_id) select $1, $2 \
+             where exists (select 1 from users) and not exists (select 1 from guilds)",
+            &[&guild_id, &channel_id],
+        )
+        .await?;
+    if inserted == 0 {
+        return Ok(());
+    }
+
+    let users = client
+        .execute(
+            "insert into guild_users (guild_id, discord_id) select $1, discord_id from users \
+             on conflict do nothing",
+            &[&guild_id],
+        )
+        .await?;
+    println!(
+        "Routed {} existing players to legacy server {}",
+        users, guild_id
+    );
+    Ok(())
+}
+
+/// Once per poll interval, checks for new matches and posts every queued
+/// match that's due. Failed posts stay queued and are retried later.
+async fn send_matches(
+    mut client: tokio_postgres::Client,
+    http: Arc<Http>,
+    config: Arc<Config>,
+    api: Arc<dyn HaloApi>,
+) {
+    let mut interval = time::interval(config.poller.interval());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(why) = match_checker::poll(&mut client, api.as_ref(), &config).await {
+            println!("Failed checking for new matches: {}", why);
+        }
+
+        let ready = match match_queue::ready(&client).await {
+            Ok(ready) => ready,
+            Err(why) => {
+                println!("Failed loading queued matches: {}", why);
+                continue;
+            }
+        };
+
+        for queued in ready {
+            let result = match send_match(&mut client, api.as_ref(), &http, &queued).await {
+                Ok(()) => match_queue::done(&client, &queued.match_id).await,
+                Err(why) => {
+                    println!("Failed posting match {}: {}", queued.match_id, why);
+                    match match_queue::failed(&client, &queued).await {
+                        Ok(true) => {
+                            println!(
+                                "Giving up on match {} after {} attempts",
+                                queued.match_id,
+                                queued.attempts + 1
+                            );
+                            Ok(())
+                        }
+                        Ok(false) => Ok(()),
+                        Err(why) => Err(why),
+                    }
+                }
+            };
+            if let Err(why) = result {
+                println!("Failed updating queued match {}: {}", queued.match_id, why);
+            }
+        }
+    }
+}
 
-    futures::pin_mut!(new_matches);
+/// The registered players from one match to post in a guild's channel.
+struct ChannelPost<'a> {
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    players: Vec<&'a (String, Data)>,
+}
 
-    let channel = ChannelId(931701787658965032);
+/// Stores one match for every registered player in it, then posts it to
+/// every channel of a guild any of the players who want it posted are in. Each channel gets a single embed covering the players
+/// registered in that guild.
+///
+/// Channels it's already been posted in are skipped, so it can be retried
+/// after some channels failed. Fails if any channel did.
+async fn send_match(
+    client: &mut tokio_postgres::Client,
+    api: &dyn HaloApi,
+    http: &Arc<Http>,
+    queued: &QueuedMatch,
+) -> error::Result<()> {
+    // Players who unregistered since the match was queued are left out, so
+    // it doesn't store their lines again.
+    let registered = registered_gamertags(client, queued).await?;
+    let is_registered = |player: &&(String, Data)| registered.contains(&player.0.to_lowercase());
+
+    let game = match queued.all_players().find(is_registered) {
+        Some((_, game)) => game,
+        None => return Ok(()),
+    };
+    let match_response = api.get_match(&game.id).await?;
+    let lobby = &match_response.data.players;
+    if lobby.is_empty() {
+        return Err(CortanaError::EmptyMatch {
+            match_id: game.id.clone(),
+        });
+    }
 
-    while let Some(game) = new_matches.next().await {
-        println!("{}", game.additional.gamertag);
+    for (gamertag, game) in queued.all_players().filter(is_registered) {
+        if let Err(why) = history::store_match(client, gamertag, game, &match_response).await {
+            println!("Failed storing match {} for {}: {}", game.id, gamertag, why);
+        }
+    }
 
-        let gamertag = game.additional.gamertag;
-        let game = game.data.first().expect("not at least one game");
+    let mut channels: Vec<ChannelPost> = Vec::new();
+    let mut ranked = Vec::new();
+    let mut failure = None;
+    for player in queued.players.iter().filter(is_registered) {
+        let (gamertag, game) = player;
+
+        // A failed lookup only holds back this player's channels, which are
+        // posted in when the match is retried.
+        let destinations = match get_destinations(gamertag, client).await {
+            Ok(destinations) => destinations,
+            Err(why) => {
+                println!("Failed getting destinations for {}: {}", gamertag, why);
+                failure = Some(why.into());
+                continue;
+            }
+        };
+        for destination in destinations {
+            let channel = destination.channel_id;
+            if queued.posted_channels.contains(&channel) {
+                continue;
+            }
+            match channels.iter_mut().find(|post| post.channel_id == channel) {
+                Some(post) => post.players.push(player),
+                None => channels.push(ChannelPost {
+                    guild_id: destination.guild_id,
+                    channel_id: channel,
+                    players: vec![player],
+                }),
+            }
+            if let Some(csr) = match_card::player_csr(game) {
+                ranked.push((destination, gamertag, csr));
+            }
+        }
+    }
 
-        let match_response = get_match(&game.id)
+    let mut posted = Vec::new();
+    for post in channels {
+        let (guild_id, channel) = (post.guild_id, post.channel_id);
+        let template = embed_template::load(client, guild_id)
             .await
-            .expect(&format!("match with id {}", &game.id));
-
-        let projected_to_win = is_projected_to_win(&match_response, game.player.team.id);
-        let overall_damage: usize = match_response
-            .data
-            .players
-            .iter()
-            .map(|player| player.stats.core.damage.dealt)
-            .sum();
-
-        let avg_damage = overall_damage / match_response.data.players.len();
-
-        let overall_kills: usize = match_response
-            .data
-            .players
-            .iter()
-            .map(|player| player.stats.core.summary.kills)
-            .sum();
-
-        let avg_kills = overall_kills as f64 / match_response.data.players.len() as f64;
-        let avg_kpm = avg_kills / (game.duration.seconds as f64 / 60.0);
-
-        if let Err(why) = send_match_results(
-            &http,
-            channel,
-            game,
-            &gamertag,
-            projected_to_win,
-            avg_damage,
-            avg_kpm,
+            .unwrap_or_else(|why| {
+                println!("Failed loading embed template for {}: {}", guild_id, why);
+                Template::default()
+            });
+        let result = match post.players.as_slice() {
+            [(gamertag, game)] => {
+                send_match_results(
+                    api,
+                    http,
+                    channel,
+                    game,
+                    gamertag,
+                    &match_response,
+                    &template,
+                )
+                .await
+            }
+            _ => send_group_results(http, channel, &post.players, &match_response, &template).await,
+        };
+
+        match result {
+            Ok(_) => {
+                posted.push(channel);
+                if let Err(why) = match_queue::posted_in(client, &game.id, channel).await {
+                    println!(
+                        "Failed recording post of {} in {}: {}",
+                        game.id, channel, why
+                    );
+                }
+            }
+            Err(why) => {
+                println!("Failed sending message to {}: {}", channel, why);
+                failure = Some(why);
+            }
+        }
+    }
+
+    for (destination, gamertag, csr) in ranked {
+        let change = RankChange::from_csr(csr).filter(|_| posted.contains(&destination.channel_id));
+        if let Some(change) = change {
+            let result = rank_change::announce_rank_change(
+                http,
+                destination.channel_id,
+                gamertag,
+                csr,
+                change,
+            )
+            .await;
+            if let Err(why) = result {
+                println!(
+                    "Failed announcing rank change to {}: {}",
+                    destination.channel_id, why
+                );
+            }
+        }
+
+        let result = role_sync::sync_member(
+            http,
+            client,
+            destination.guild_id,
+            destination.user_id,
+            csr.post_match.tier,
         )
-        .await
-        {
-            println!("Failed sending message: {}", why)
+        .await;
+        if let Err(why) = result {
+            println!(
+                "Failed syncing rank role for {} in {}: {}",
+                destination.user_id, destination.guild_id, why
+            );
         }
     }
+
+    match failure {
+        Some(why) => Err(why),
+        None => Ok(()),
+    }
+}
+
+/// The lowercased gamertags of a queued match's players that are still
+/// registered.
+async fn registered_gamertags(
+    client: &tokio_postgres::Client,
+    queued: &QueuedMatch,
+) -> error::Result<Vec<String>> {
+    let gamertags: Vec<String> = queued
+        .all_players()
+        .map(|(gamertag, _)| gamertag.to_lowercase())
+        .collect();
+    let rows = client
+        .query(
+            "select gamertag from users where gamertag = any($1)",
+            &[&gamertags],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// One embed for several registered players in the same match, with a
+/// field per player.
+async fn send_group_results(
+    http: &Arc<Http>,
+    channel_id: ChannelId,
+    players: &[&(String, Data)],
+    match_response: &MatchResponse,
+    template: &Template,
+) -> error::Result<Message> {
+    let game = &players[0].1;
+    let card = match_card::render_group_embed(players, match_response, template);
+    log_unknown_medals(&card);
+    let embed = card.to_embed();
+
+    let message = channel_id
+        .send_message(http, |m| {
+            m.set_embed(embed)
+                .components(|c| scoreboard::add_button(c, &game.id))
+        })
+        .await?;
+
+    Ok(message)
 }
 
-fn is_projected_to_win(match_response: &MatchResponse, team_id: usize) -> bool {
-    let (my_team, other_team): (Vec<_>, Vec<_>) = match_response
-        .data
-        .teams
-        .details
+/// Where a player's matches get posted: a server they're registered in and
+/// its match update channel.
+struct Destination {
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+}
+
+async fn get_destinations(
+    gamertag: &str,
+    client: &tokio_postgres::Client,
+) -> Result<Vec<Destination>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "select g.guild_id, g.channel_id, u.discord_id from guilds g \
+             join guild_users gu on gu.guild_id = g.guild_id \
+             join users u on u.discord_id = gu.discord_id \
+             where u.gamertag = $1",
+            &[&gamertag.to_lowercase()],
+        )
+        .await?;
+
+    Ok(rows
         .iter()
-        .partition(|t| t.team.id == team_id);
-    let my_team = my_team.first().expect("my_team");
-    let other_team = other_team.first().expect("other_team");
-    my_team.team.skill.mmr > other_team.team.skill.mmr
+        .map(|row| Destination {
+            guild_id: GuildId(row.get::<_, i64>(0) as u64),
+            channel_id: ChannelId(row.get::<_, i64>(1) as u64),
+            user_id: UserId(row.get::<_, i64>(2) as u64),
+        })
+        .collect())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let sql_client = Arc::new(connect_to_db().await?);
+    let config = Arc::new(Config::load()?);
+    let sql_client = Arc::new(connect_to_db(&config).await?);
+    backfill_legacy_guild(&sql_client, &config).await?;
+    // The poller gets its own connection so it can use transactions without
+    // holding up everything else.
+    let poller_client = connect_to_db(&config).await?;
+
+    let api: Arc<dyn HaloApi> = match &config.halo.fixtures_dir {
+        Some(dir) => {
+            println!("Replaying Halo API responses from {}", dir.display());
+            Arc::new(FixtureHaloApi::from_dir(dir)?)
+        }
+        None => Arc::new(HttpHaloApi::new(&config.halo)?),
+    };
 
     // Configure the client with your Discord bot token in the environment.
     let token = env::var("DISCORD_TOKEN")?;
     let mut client = Client::builder(&token)
         .event_handler(Handler {
             client: Arc::clone(&sql_client),
+            config: Arc::clone(&config),
+            api: Arc::clone(&api),
         })
-        .application_id(928312197489229825)
+        .application_id(config.discord.application_id)
         .await?;
 
     let http = Arc::clone(&client.cache_and_http.http);
 
-    if let (Err(why), _) = tokio::join!(client.start(), send_matches(sql_client, http)) {
+    if let (Err(why), _, _, _, _) = tokio::join!(
+        client.start(),
+        role_sync::sync_rank_roles(
+            Arc::clone(&sql_client),
+            Arc::clone(&http),
+            Arc::clone(&config)
+        ),
+        sessions::summarize_sessions(
+            Arc::clone(&sql_client),
+            Arc::clone(&http),
+            Arc::clone(&config)
+        ),
+        recap::post_weekly_recaps(
+            Arc::clone(&sql_client),
+            Arc::clone(&http),
+            Arc::clone(&config)
+        ),
+        send_matches(poller_client, http, config, api)
+    ) {
         println!("Client error: {:?}", why);
     }
 
@@ -0,0 +1,777 @@
+use crate::embed_template::{Element, Template};
+use crate::error::{CortanaError, Result};
+use crate::match_response::MatchResponse;
+use crate::matches_response::{Csr, Data, Input, Outcome, Queue};
+use crate::{rank, stats};
+use chrono::{DateTime, Utc};
+use serenity::builder::CreateEmbed;
+
+static EMOJIS: [&str; 59] = [
+    "<:AchillesSpine:932071031106072607>",
+    "<:AlwaysRotating:939744585675067393>",
+    "<:BackSmack:932071030825058384>",
+    "<:BankShot:938209152675774475>",
+    "<:Bodyguard:938209152726085712>",
+    "<:Bomber:938136842274996225>",
+    "<:Boogeyman:931631927486734417>",
+    "<:BoomBlock:932071031072518144>",
+    "<:Boxer:932071030766333982>",
+    "<:ClockStop:940066017982427146>",
+    "<:ClusterLuck:940090243145682954>",
+    "<:Demon:931631928120066088>",
+    "<:DoubleKill:931631928023597066>",
+    "<:Extermination:931631929239949343>",
+    "<:Fastball:932071030950867057>",
+    "<:FlagJoust:939786696910860341>",
+    "<:FromtheGrave:932071030795677717>",
+    "<:Fumble:932071030762119168>",
+    "<:GoalLineStand:939786660990836816>",
+    "<:GrappleJack:931631927788728342>",
+    "<:Grenadier:938136887430893668>",
+    "<:GrimReaper:931631928136826900>",
+    "<:GuardianAngel:932071031152214016>",
+    "<:HailMary:938209152105320510>",
+    "<:HoldThis:938209152327647272>",
+    "<:KillingFrenzy:931631928497541171>",
+    "<:KillingSpree:931631928476598302>",
+    "<:Killionaire:931631929311244370>",
+    "<:Killjoy:931631928585642055>",
+    "<:Killtastrophe:931631929667780608>",
+    "<:Killtrocity:931631929642590228>",
+    "<:LastShot:932071030862790706>",
+    "<:Marksman:932071031047340073>",
+    "<:MindtheGap:938426546157412422>",
+    "<:NadeShot:939786758177058837>",
+    "<:Nightmare:931631929067986944>",
+    "<:Ninja:931631929697136770>",
+    "<:NoScope:931631929139277874>",
+    "<:OfftheRack:938136887229562971>",
+    "<:Overkill:931631929617448970>",
+    "<:Pancake:940376937065480263>",
+    "<:Perfect:932071031181570078>",
+    "<:Perfection:931631929646796870>",
+    "<:Quigley:931631929663569980>",
+    "<:Rampage:931631929420296202>",
+    "<:Reversal:932071031101853736>",
+    "<:RunningRiot:931631929621622875>",
+    "<:Scattergunner:938136887288287303>",
+    "<:ShotCaller:938813060506849390>",
+    "<:SneakKing:939503925302820935>",
+    "<:Snipe:931631929575473192>",
+    "<:Steaktacular:938136887611228240>",
+    "<:Stick:937788737583202406>",
+    "<:StoppedShort:938677766587572274>",
+    "<:StraightBalling:938426609864679424>",
+    "<:TripleKill:931631929185411124>",
+    "<:Whiplash:938813020107329606>",
+    "<:Wingman:932071030732767283>",
+    "<:YardSale:932071031068307456>",
+];
+
+/// The emoji for a medal, matched on its name without spaces.
+pub fn name_to_emoji(name: &str) -> Option<&'static str> {
+    let name: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+    EMOJIS
+        .iter()
+        .find(|emoji| emoji.contains(&format!(":{}:", name)))
+        .copied()
+}
+
+pub fn outcome_label(outcome: &Outcome) -> (&'static str, (u8, u8, u8)) {
+    match outcome {
+        Outcome::Win => ("WON", (0, 255, 0)),
+        Outcome::Loss => ("LOST", (255, 0, 0)),
+        Outcome::Draw => ("TIED", (0, 0, 255)),
+        Outcome::Left => ("LEFT", (0, 0, 255)),
+    }
+}
+
+/// The player's medals as emoji with counts. Medals without an emoji are
+/// left out and added to `unknown`.
+fn medal_string(data: &Data, unknown: &mut Vec<String>) -> String {
+    data.player
+        .stats
+        .core
+        .breakdowns
+        .medals
+        .iter()
+        .filter_map(|m| match name_to_emoji(m.name.as_str()) {
+            Some(emoji) => Some(format!("{}x{}", emoji, m.count)),
+            None => {
+                unknown.push(m.name.clone());
+                None
+            }
+        })
+        .reduce(|mut acc, a| {
+            acc.push(' ');
+            acc.push_str(a.as_str());
+            acc
+        })
+        .unwrap_or_else(|| "Nothing special 😔".to_owned())
+}
+
+/// The player's CSR before and after the match, for ranked matches.
+pub fn player_csr(data: &Data) -> Option<&Csr> {
+    data.player
+        .progression
+        .as_ref()
+        .map(|progression| &progression.csr)
+}
+
+/// The queue and input for ranked playlists, otherwise the playlist's name.
+fn playlist_label(data: &Data) -> String {
+    let playlist = match &data.details.playlist {
+        Some(playlist) => playlist,
+        None => return "Custom game".to_owned(),
+    };
+    if !playlist.properties.ranked {
+        return playlist.name.clone();
+    }
+
+    ranked_playlist_label(
+        playlist.properties.queue.as_ref().map(Queue::as_str),
+        playlist.properties.input.as_ref().map(Input::as_str),
+    )
+}
+
+/// Names a ranked playlist by its queue and input, as stored in the match
+/// history.
+pub fn ranked_playlist_label(queue: Option<&str>, input: Option<&str>) -> String {
+    let queue = match queue {
+        Some("solo-duo") => "Solo/Duo",
+        Some("open") => "Open",
+        _ => "Unknown",
+    };
+    let input = match input {
+        Some("controller") => "Controller",
+        Some("mnk") => "M+K",
+        Some("crossplay") => "Crossplay",
+        _ => "Unknown",
+    };
+    format!("{} {}", queue, input)
+}
+
+/// Whether the player's team went in with the higher MMR, or `None` when
+/// there is no opposing team to compare against (e.g. free-for-all).
+fn is_projected_to_win(match_response: &MatchResponse, team_id: usize) -> Option<bool> {
+    let (my_team, other_team): (Vec<_>, Vec<_>) = match_response
+        .data
+        .teams
+        .details
+        .iter()
+        .partition(|t| t.team.id == team_id);
+    let my_team = my_team.first()?;
+    let other_team = other_team.first()?;
+    Some(my_team.team.skill.mmr > other_team.team.skill.mmr)
+}
+
+/// Per-player averages across some of a match's players.
+pub struct Averages {
+    pub damage: usize,
+    pub kills: f64,
+    pub kpm: f64,
+}
+
+impl Averages {
+    fn new(damage: usize, kills: usize, players: usize, minutes: f64) -> Averages {
+        let kills = kills as f64 / players as f64;
+        Averages {
+            damage: damage / players,
+            kills,
+            kpm: kills / minutes,
+        }
+    }
+}
+
+/// Averages across the whole lobby, or `None` if nobody played.
+fn lobby_averages(match_response: &MatchResponse, minutes: f64) -> Option<Averages> {
+    let lobby = &match_response.data.players;
+    if lobby.is_empty() {
+        return None;
+    }
+    Some(Averages::new(
+        lobby
+            .iter()
+            .map(|player| player.stats.core.damage.dealt)
+            .sum(),
+        lobby
+            .iter()
+            .map(|player| player.stats.core.summary.kills)
+            .sum(),
+        lobby.len(),
+        minutes,
+    ))
+}
+
+/// Averages for `team_id` and for everyone against it, from the teams'
+/// totals. `None` when the match has no such team or nobody against it.
+fn team_averages(
+    match_response: &MatchResponse,
+    team_id: usize,
+    minutes: f64,
+) -> Option<(Averages, Averages)> {
+    let details = &match_response.data.teams.details;
+    let team = details.iter().find(|team| team.team.id == team_id)?;
+    let enemies: Vec<_> = details
+        .iter()
+        .filter(|team| team.team.id != team_id)
+        .collect();
+
+    let players = &match_response.data.players;
+    let team_size = players
+        .iter()
+        .filter(|player| player.team.id == team_id)
+        .count();
+    let enemy_size = players.len() - team_size;
+    if team_size == 0 || enemy_size == 0 || enemies.is_empty() {
+        return None;
+    }
+
+    let team = Averages::new(
+        team.stats.core.damage.dealt,
+        team.stats.core.summary.kills,
+        team_size,
+        minutes,
+    );
+    let enemy = Averages::new(
+        enemies
+            .iter()
+            .map(|team| team.stats.core.damage.dealt)
+            .sum(),
+        enemies
+            .iter()
+            .map(|team| team.stats.core.summary.kills)
+            .sum(),
+        enemy_size,
+        minutes,
+    );
+    Some((team, enemy))
+}
+
+/// What a match card needs beyond the player's `Data`, worked out from the
+/// full match and the Halo API ahead of rendering.
+pub struct MatchContext {
+    pub gamertag: String,
+    pub emblem_url: Option<String>,
+    pub projected_to_win: Option<bool>,
+    pub lobby: Averages,
+    pub teams: Option<(Averages, Averages)>,
+}
+
+impl MatchContext {
+    pub fn new(
+        gamertag: &str,
+        data: &Data,
+        match_response: &MatchResponse,
+        emblem_url: Option<String>,
+    ) -> Result<MatchContext> {
+        let minutes = data.duration.seconds.max(1) as f64 / 60.0;
+        let lobby =
+            lobby_averages(match_response, minutes).ok_or_else(|| CortanaError::EmptyMatch {
+                match_id: data.id.clone(),
+            })?;
+
+        Ok(MatchContext {
+            gamertag: gamertag.to_owned(),
+            emblem_url,
+            projected_to_win: is_projected_to_win(match_response, data.player.team.id),
+            lobby,
+            teams: team_averages(match_response, data.player.team.id, minutes),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CardField {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+/// Everything a match post shows, independent of Discord.
+#[derive(Debug, PartialEq)]
+pub struct MatchCard {
+    pub title: String,
+    pub color: (u8, u8, u8),
+    pub description: Option<String>,
+    pub fields: Vec<CardField>,
+    pub image_url: Option<String>,
+    pub url: String,
+    pub timestamp: DateTime<Utc>,
+    pub thumbnail_url: Option<String>,
+    /// Medals left out for having no emoji, for the caller to report.
+    pub unknown_medals: Vec<String>,
+}
+
+impl MatchCard {
+    fn field(&mut self, name: &str, value: String) {
+        self.fields.push(CardField {
+            name: name.to_owned(),
+            value,
+            inline: true,
+        });
+    }
+
+    fn new(title: String, color: (u8, u8, u8), data: &Data) -> MatchCard {
+        MatchCard {
+            title,
+            color,
+            description: None,
+            fields: Vec::new(),
+            image_url: None,
+            url: format!("https://halotracker.com/halo-infinite/match/{}", data.id),
+            timestamp: data.played_at,
+            thumbnail_url: None,
+            unknown_medals: Vec::new(),
+        }
+    }
+
+    pub fn to_embed(&self) -> CreateEmbed {
+        let mut embed = CreateEmbed::default();
+        embed
+            .title(&self.title)
+            .color(self.color)
+            .fields(
+                self.fields
+                    .iter()
+                    .map(|field| (&field.name, &field.value, field.inline)),
+            )
+            .url(&self.url)
+            .timestamp(&self.timestamp);
+        if let Some(description) = &self.description {
+            embed.description(description);
+        }
+        if let Some(image_url) = &self.image_url {
+            embed.image(image_url);
+        }
+        if let Some(thumbnail_url) = &self.thumbnail_url {
+            embed.thumbnail(thumbnail_url);
+        }
+        embed
+    }
+}
+
+/// One player's result in a match, showing what `template` lists in its
+/// order.
+pub fn render_match_embed(data: &Data, context: &MatchContext, template: &Template) -> MatchCard {
+    let stats = &data.player.stats.core;
+    let (result, color) = outcome_label(&data.player.outcome);
+    let csr = player_csr(data);
+
+    let mut card = MatchCard::new(
+        format!(
+            "{} {} a game of {}!",
+            context.gamertag, result, data.details.category.name
+        ),
+        color,
+        data,
+    );
+
+    for element in template.elements() {
+        match element {
+            Element::Playlist => card.field("Playlist", playlist_label(data)),
+            Element::Map => card.field("Map", data.details.map.name.clone()),
+            Element::Rank => {
+                if let Some(csr) = csr {
+                    let rank = rank::rank_label(&csr.post_match.tier, csr.post_match.sub_tier);
+                    card.field("Rank", format!("{} ({})", rank, csr.post_match.value));
+                }
+            }
+            Element::Kda => card.field(
+                "KDA",
+                format!(
+                    "{}/{}/{} ({})",
+                    stats.summary.kills, stats.summary.deaths, stats.summary.assists, stats.kda
+                ),
+            ),
+            Element::Projection => {
+                let projected_to_win = match context.projected_to_win {
+                    Some(true) => "Yes",
+                    Some(false) => "No",
+                    None => "N/A",
+                };
+                card.field("Projected to Win?", projected_to_win.to_owned());
+            }
+            Element::CsrChange => {
+                if let Some(csr) = csr {
+                    let change = csr.post_match.value - csr.pre_match.value;
+                    card.field("CSR change", stats::format_change(change as i64));
+                }
+            }
+            Element::Kpm => {
+                let kpm = stats.summary.kills as f64 / (data.duration.seconds.max(1) as f64 / 60.0);
+                card.field(
+                    "KPM / Avg",
+                    format!("{:.1} / {:.1}", kpm, context.lobby.kpm),
+                );
+            }
+            Element::Accuracy => {
+                card.field("Accuracy", format!("{}%", stats.shots.accuracy.round()))
+            }
+            Element::Damage => card.field(
+                "Damage Dealt / Avg",
+                format!("{} / {}", stats.damage.dealt, context.lobby.damage),
+            ),
+            Element::Medals => {
+                let medals = medal_string(data, &mut card.unknown_medals);
+                card.field("Medals", medals);
+            }
+            Element::TeamAverages => {
+                if let Some((team, enemy)) = &context.teams {
+                    card.field(
+                        "Team / Enemy Avg",
+                        format!(
+                            "Damage {} / {}\nKills {:.1} / {:.1}\nKPM {:.1} / {:.1}",
+                            team.damage, enemy.damage, team.kills, enemy.kills, team.kpm, enemy.kpm
+                        ),
+                    );
+                }
+            }
+            Element::MapImage => {
+                card.image_url = Some(data.details.map.asset.thumbnail_url.clone())
+            }
+            Element::Emblem => card.thumbnail_url = context.emblem_url.clone(),
+        }
+    }
+
+    card
+}
+
+/// Several registered players in the same match, with each team's result in
+/// the description and a field per player, showing what `template` lists in
+/// its order. Team averages and emblems belong to a single player, so group
+/// posts leave them out.
+pub fn render_group_embed(
+    players: &[&(String, Data)],
+    match_response: &MatchResponse,
+    template: &Template,
+) -> MatchCard {
+    let game = &players[0].1;
+
+    let names: Vec<&str> = players
+        .iter()
+        .map(|(gamertag, _)| gamertag.as_str())
+        .collect();
+    let names = match names.split_last() {
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        None => String::new(),
+    };
+
+    let same_outcome = players
+        .iter()
+        .all(|(_, data)| data.player.outcome == game.player.outcome);
+    let (result, color) = outcome_label(&game.player.outcome);
+    let title = if same_outcome {
+        format!(
+            "{} {} a game of {}!",
+            names, result, game.details.category.name
+        )
+    } else {
+        format!("{} played a game of {}!", names, game.details.category.name)
+    };
+
+    let mut description = Vec::new();
+    for element in template.elements() {
+        match element {
+            Element::Playlist => description.push(playlist_label(game)),
+            Element::Map => description.push(game.details.map.name.clone()),
+            _ => {}
+        }
+    }
+    for (_, data) in players {
+        let team_id = data.player.team.id;
+        let team = match match_response
+            .data
+            .teams
+            .details
+            .iter()
+            .find(|team| team.team.id == team_id)
+        {
+            Some(team) => team,
+            None => continue,
+        };
+        let (result, _) = outcome_label(&data.player.outcome);
+        let projected = match is_projected_to_win(match_response, team_id) {
+            _ if !template.shows(Element::Projection) => "",
+            Some(true) => " (projected to win)",
+            Some(false) => " (projected to lose)",
+            None => "",
+        };
+        let line = format!(
+            "Team {} {} with {:.0} MMR{}",
+            team.team.name, result, team.team.skill.mmr, projected
+        );
+        if !description.contains(&line) {
+            description.push(line);
+        }
+    }
+
+    let mut card = MatchCard::new(title, color, game);
+    if !description.is_empty() {
+        card.description = Some(description.join("\n"));
+    }
+    if template.shows(Element::MapImage) {
+        card.image_url = Some(game.details.map.asset.thumbnail_url.clone());
+    }
+
+    for (gamertag, data) in players {
+        let (result, _) = outcome_label(&data.player.outcome);
+        let value = group_line(data, template, &mut card.unknown_medals);
+        card.fields.push(CardField {
+            name: format!("{} {}", gamertag, result),
+            value,
+            inline: false,
+        });
+    }
+
+    card
+}
+
+/// A player's field in a group post. Rank and CSR change share the first
+/// line and medals get the last, with the stats in between.
+fn group_line(data: &Data, template: &Template, unknown_medals: &mut Vec<String>) -> String {
+    let stats = &data.player.stats.core;
+    let csr = player_csr(data);
+
+    let mut rank = Vec::new();
+    let mut numbers = Vec::new();
+    let mut medals = None;
+    for element in template.elements() {
+        match element {
+            Element::Rank => {
+                if let Some(csr) = csr {
+                    rank.push(format!(
+                        "{} ({})",
+                        rank::rank_label(&csr.post_match.tier, csr.post_match.sub_tier),
+                        csr.post_match.value
+                    ));
+                }
+            }
+            Element::CsrChange => {
+                if let Some(csr) = csr {
+                    let change = csr.post_match.value - csr.pre_match.value;
+                    rank.push(stats::format_change(change as i64));
+                }
+            }
+            Element::Kda => numbers.push(format!(
+                "KDA {}/{}/{} ({})",
+                stats.summary.kills, stats.summary.deaths, stats.summary.assists, stats.kda
+            )),
+            Element::Kpm => {
+                let kpm = stats.summary.kills as f64 / (data.duration.seconds.max(1) as f64 / 60.0);
+                numbers.push(format!("{:.1} KPM", kpm));
+            }
+            Element::Accuracy => {
+                numbers.push(format!("{}% accuracy", stats.shots.accuracy.round()))
+            }
+            Element::Damage => numbers.push(format!("{} damage", stats.damage.dealt)),
+            Element::Medals => medals = Some(medal_string(data, unknown_medals)),
+            _ => {}
+        }
+    }
+
+    let lines: Vec<String> = vec![rank.join(" "), numbers.join(" · ")]
+        .into_iter()
+        .chain(medals)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if lines.is_empty() {
+        return "\u{200b}".to_owned();
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emblem_response::EmblemResponse;
+    use crate::matches_response::MatchesResponse;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::Path;
+
+    fn read<T: serde::de::DeserializeOwned>(path: &Path) -> T {
+        let contents = fs::read_to_string(path)
+            .unwrap_or_else(|why| panic!("reading {}: {}", path.display(), why));
+        serde_json::from_str(&contents)
+            .unwrap_or_else(|why| panic!("parsing {}: {}", path.display(), why))
+    }
+
+    /// Every recorded match by ID, with the line of each player recorded in
+    /// it, matchmade and custom alike.
+    fn recorded_matches(fixtures: &Path) -> BTreeMap<String, Vec<(String, Data)>> {
+        let mut recorded: BTreeMap<String, Vec<(String, Data)>> = BTreeMap::new();
+        for dir in ["matches", "custom"] {
+            for entry in fs::read_dir(fixtures.join(dir)).unwrap() {
+                let matches: MatchesResponse = read(&entry.unwrap().path());
+                for data in matches.data {
+                    recorded
+                        .entry(data.id.clone())
+                        .or_default()
+                        .push((matches.additional.gamertag.clone(), data));
+                }
+            }
+        }
+        for players in recorded.values_mut() {
+            players.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        recorded
+    }
+
+    fn check_golden(golden: &Path, card: &MatchCard) {
+        let rendered = format!("{:#?}\n", card);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(golden, &rendered).unwrap();
+        } else {
+            let expected = fs::read_to_string(golden)
+                .unwrap_or_else(|why| panic!("reading {}: {}", golden.display(), why));
+            assert_eq!(rendered, expected, "{} is out of date", golden.display());
+        }
+    }
+
+    /// Renders every recorded match, once per recorded player and once for
+    /// all of them together when there are several, and compares the cards
+    /// with their golden files in `fixtures/golden`. Run with
+    /// `UPDATE_GOLDEN=1` to rewrite them after an intended change.
+    #[test]
+    fn match_cards_match_golden_files() {
+        let fixtures = Path::new("fixtures");
+        let golden = fixtures.join("golden");
+
+        let mut checked = 0;
+        for (match_id, players) in recorded_matches(fixtures) {
+            let match_response: MatchResponse =
+                read(&fixtures.join("match").join(format!("{}.json", match_id)));
+
+            for (gamertag, data) in &players {
+                let emblem: EmblemResponse = read(
+                    &fixtures
+                        .join("appearance")
+                        .join(format!("{}.json", gamertag.to_lowercase())),
+                );
+                let context = MatchContext::new(
+                    gamertag,
+                    data,
+                    &match_response,
+                    Some(emblem.data.emblem_url),
+                )
+                .unwrap();
+                check_golden(
+                    &golden.join(format!("{}-{}.txt", gamertag.to_lowercase(), match_id)),
+                    &render_match_embed(data, &context, &Template::default()),
+                );
+                checked += 1;
+            }
+
+            if players.len() > 1 {
+                let players: Vec<&(String, Data)> = players.iter().collect();
+                check_golden(
+                    &golden.join(format!("group-{}.txt", match_id)),
+                    &render_group_embed(&players, &match_response, &Template::default()),
+                );
+            }
+        }
+        assert!(checked > 0, "no recorded matches to render");
+    }
+
+    #[test]
+    fn templates_pick_and_order_fields() {
+        let fixtures = Path::new("fixtures");
+        let matches: MatchesResponse = read(&fixtures.join("matches").join("billzabob.json"));
+        let data = &matches.data[0];
+        let match_response: MatchResponse =
+            read(&fixtures.join("match").join(format!("{}.json", data.id)));
+        let context = MatchContext::new(
+            "Billzabob",
+            data,
+            &match_response,
+            Some("https://example/emblem.png".to_owned()),
+        )
+        .unwrap();
+
+        let template = Template::new(vec![Element::Medals, Element::Map, Element::Kda]);
+        let card = render_match_embed(data, &context, &template);
+        let names: Vec<&str> = card
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect();
+        assert_eq!(names, ["Medals", "Map", "KDA"]);
+        assert_eq!(card.fields[1].value, "Streets");
+        assert_eq!(card.image_url, None);
+        assert_eq!(card.thumbnail_url, None);
+    }
+
+    #[test]
+    fn group_posts_follow_the_template() {
+        let fixtures = Path::new("fixtures");
+        let recorded = recorded_matches(fixtures);
+        let (match_id, players) = recorded
+            .iter()
+            .find(|(_, players)| players.len() > 1)
+            .unwrap();
+        let match_response: MatchResponse =
+            read(&fixtures.join("match").join(format!("{}.json", match_id)));
+        let players: Vec<&(String, Data)> = players.iter().collect();
+
+        let template = Template::new(vec![Element::Damage, Element::Kda]);
+        let card = render_group_embed(&players, &match_response, &template);
+        let (_, data) = players[0];
+        let stats = &data.player.stats.core;
+        assert_eq!(
+            card.fields[0].value,
+            format!(
+                "{} damage · KDA {}/{}/{} ({})",
+                stats.damage.dealt,
+                stats.summary.kills,
+                stats.summary.deaths,
+                stats.summary.assists,
+                stats.kda
+            )
+        );
+        assert_eq!(card.image_url, None);
+        assert!(card
+            .description
+            .iter()
+            .all(|description| !description.contains("projected")));
+    }
+
+    #[test]
+    fn empty_matches_have_no_context() {
+        let fixtures = Path::new("fixtures");
+        let matches: MatchesResponse = read(&fixtures.join("matches").join("billzabob.json"));
+        let data = &matches.data[0];
+        let mut match_response: MatchResponse =
+            read(&fixtures.join("match").join(format!("{}.json", data.id)));
+        match_response.data.players.clear();
+
+        let result = MatchContext::new("Billzabob", data, &match_response, None);
+        assert!(matches!(result, Err(CortanaError::EmptyMatch { .. })));
+    }
+
+    #[test]
+    fn medals_without_an_emoji_are_left_out_and_reported() {
+        let fixtures = Path::new("fixtures");
+        let mut matches: MatchesResponse = read(&fixtures.join("matches").join("billzabob.json"));
+        let data = &mut matches.data[0];
+        data.player.stats.core.breakdowns.medals = vec![
+            crate::matches_response::Medal {
+                name: "Double Kill".to_owned(),
+                count: 2,
+            },
+            crate::matches_response::Medal {
+                name: "Brand New Medal".to_owned(),
+                count: 1,
+            },
+        ];
+        let match_response: MatchResponse =
+            read(&fixtures.join("match").join(format!("{}.json", data.id)));
+        let context = MatchContext::new("Billzabob", data, &match_response, None).unwrap();
+
+        let card = render_match_embed(data, &context, &Template::new(vec![Element::Medals]));
+
+        assert_eq!(card.fields[0].value, "<:DoubleKill:931631928023597066>x2");
+        assert_eq!(card.unknown_medals, ["Brand New Medal"]);
+    }
+}
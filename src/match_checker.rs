@@ -1,82 +1,194 @@
-use crate::matches_request::{Limit, MatchesRequest};
-use crate::matches_response::{Data, MatchesResponse, Outcome};
-use async_stream::stream;
-use futures::{future, FutureExt, Stream};
-use std::error::Error;
+use crate::config::Config;
+use crate::error::Result;
+use crate::halo_api::HaloApi;
+use crate::match_queue;
+use crate::matches_request::Mode;
+use crate::matches_response::{Data, MatchesResponse};
+use crate::settings::Filters;
+use futures::future;
+use serenity::async_trait;
 use std::time::Duration;
 use tokio::time;
-use tokio_postgres::{Client, Statement};
+use tokio_postgres::Client;
 
-pub fn check_for_new_matches(client: &Client) -> impl Stream<Item = MatchesResponse> + '_ {
-    let mut interval = time::interval(Duration::from_secs(5));
-    stream! {
-      loop {
-        interval.tick().await;
+/// How many matches to request per page when paging through a player's
+/// history.
+pub const PAGE_SIZE: usize = 25;
 
-        let statement = client
-            .prepare("update users set latest_match_id = $1 where gamertag = $2")
-            .await
-            .expect("prepare");
+/// A registered player and where the poller left off for them.
+#[derive(Clone)]
+pub struct TrackedPlayer {
+    pub gamertag: String,
+    pub latest_match_id: Option<String>,
+    pub latest_custom_match_id: Option<String>,
+    pub enabled: bool,
+    pub filters: Filters,
+}
+
+/// A player's new matches from one poll, and what to move their cursors to.
+pub struct NewGames {
+    pub response: MatchesResponse,
+    pub latest_match_id: Option<String>,
+    pub latest_custom_match_id: Option<String>,
+    pub enabled: bool,
+    pub filters: Filters,
+}
+
+impl NewGames {
+    /// Whether a match gets posted, rather than only stored in history.
+    pub fn posts(&self, data: &Data) -> bool {
+        self.enabled && self.filters.keeps(data)
+    }
+}
 
-        let new_games = get_new_games(client).await;
+/// Where the poller reads players from and queues their new matches. The
+/// database in production; tests use an in-memory one.
+#[async_trait]
+pub trait PlayerStore: Send {
+    async fn players(&mut self) -> Result<Vec<TrackedPlayer>>;
+
+    /// Queues all of a player's new matches to be stored, marking the ones
+    /// they want posted, and moves their cursors past them. Either both
+    /// happen or neither does, so a match is never skipped without being
+    /// queued.
+    async fn queue(&mut self, games: &NewGames, grace: Duration) -> Result<()>;
+}
 
-        let updates: Vec<_> = new_games
+#[async_trait]
+impl PlayerStore for Client {
+    async fn players(&mut self) -> Result<Vec<TrackedPlayer>> {
+        let rows = self
+            .query(
+                "select u.gamertag, u.latest_match_id, u.enabled, u.latest_custom_match_id, \
+                 s.categories, s.queues, s.inputs, s.outcomes \
+                 from users u left join user_settings s on s.discord_id = u.discord_id",
+                &[],
+            )
+            .await?;
+
+        Ok(rows
             .iter()
-            .map(|game| update_match(&game.0, client, &statement))
-            .collect();
+            .map(|row| TrackedPlayer {
+                gamertag: row.get(0),
+                latest_match_id: row.get(1),
+                enabled: row.get(2),
+                latest_custom_match_id: row.get(3),
+                filters: Filters::from_row(row, 4),
+            })
+            .collect())
+    }
 
-        future::join_all(updates).await;
+    async fn queue(&mut self, games: &NewGames, grace: Duration) -> Result<()> {
+        let gamertag = games.response.additional.gamertag.as_str();
+        let transaction = self.transaction().await?;
 
-        for game in new_games {
-            if game.1 {
-                yield game.0;
-            }
+        for data in &games.response.data {
+            match_queue::push(&transaction, gamertag, data, games.posts(data), grace).await?;
         }
-      }
+
+        transaction
+            .execute(
+                "update users set latest_match_id = coalesce($1, latest_match_id), \
+                 latest_custom_match_id = coalesce($2, latest_custom_match_id) \
+                 where gamertag = $3",
+                &[
+                    &games.latest_match_id,
+                    &games.latest_custom_match_id,
+                    &gamertag.to_lowercase(),
+                ],
+            )
+            .await?;
+
+        transaction.commit().await?;
+        Ok(())
     }
 }
 
-async fn update_match(
-    game: &MatchesResponse,
-    client: &Client,
-    statement: &Statement,
-) -> Result<u64, tokio_postgres::Error> {
-    let latest_match_id = game.data[0].id.as_str();
-    let gamertag = game.additional.gamertag.as_str();
+/// Checks every player for new matches and queues them, so every match is
+/// stored whether or not it's posted.
+pub async fn poll(store: &mut dyn PlayerStore, api: &dyn HaloApi, config: &Config) -> Result<()> {
+    let players = store.players().await?;
+    let new_games = get_new_games(
+        &players,
+        api,
+        config.poller.interval(),
+        config.poller.max_catch_up,
+    )
+    .await;
 
-    client
-        .execute(statement, &[&latest_match_id, &gamertag.to_lowercase()])
-        .await
+    for games in new_games {
+        if let Err(why) = store.queue(&games, config.poller.group_grace()).await {
+            println!(
+                "Failed queueing matches for {}: {}",
+                games.response.additional.gamertag, why
+            );
+        }
+    }
+
+    Ok(())
 }
 
-async fn get_new_games(client: &Client) -> Vec<(MatchesResponse, bool)> {
-    let rows = client
-        .query("select gamertag, latest_match_id, enabled from users", &[])
-        .await
-        .expect("new matches SQL");
+/// Polls every player once, spreading the requests evenly across `interval`
+/// instead of sending them all at once.
+async fn get_new_games(
+    players: &[TrackedPlayer],
+    api: &dyn HaloApi,
+    interval: Duration,
+    max_catch_up: usize,
+) -> Vec<NewGames> {
+    let spacing = interval / players.len().max(1) as u32;
 
-    let new_games: Vec<_> = rows
+    let new_games: Vec<_> = players
         .iter()
-        .map(|row| {
-            let gamertag: &str = row.get(0);
-            let last_match_id: Option<&str> = row.get(1);
-            let enabled: bool = row.get(2);
-
-            get_latest_match(gamertag).map(move |game| match game {
-                Ok(game) => {
-                    let game_data = game.data.first();
-                    if game_data.map(|d| d.id.as_str()) != last_match_id
-                        && game_data.map_or(false, |d| should_keep_match(d))
-                    {
-                        Some((game, enabled))
-                    } else {
-                        None
-                    }
-                }
+        .enumerate()
+        .map(|(i, player)| async move {
+            let gamertag = player.gamertag.as_str();
+
+            time::sleep(spacing * i as u32).await;
+
+            let matchmade = get_unseen_matches(
+                api,
+                gamertag,
+                Mode::Matchmade,
+                player.latest_match_id.as_deref(),
+                max_catch_up,
+            );
+            let mut response = match matchmade.await {
+                Ok(response) => response,
                 Err(why) => {
-                    println!("Uh oh: {}", why);
-                    None
+                    println!("Failed getting matches for {}: {}", gamertag, why);
+                    return None;
                 }
+            };
+            let latest_match_id = response.data.first().map(|data| data.id.clone());
+
+            let mut latest_custom_match_id = None;
+            let custom = get_unseen_matches(
+                api,
+                gamertag,
+                Mode::Custom,
+                player.latest_custom_match_id.as_deref(),
+                max_catch_up,
+            );
+            match custom.await {
+                Ok(mut custom) => {
+                    latest_custom_match_id = custom.data.first().map(|data| data.id.clone());
+                    response.data.append(&mut custom.data);
+                }
+                Err(why) => println!("Failed getting custom games for {}: {}", gamertag, why),
+            }
+
+            if latest_match_id.is_none() && latest_custom_match_id.is_none() {
+                return None;
+            }
+
+            response.data.sort_by_key(|data| data.played_at);
+            Some(NewGames {
+                response,
+                latest_match_id,
+                latest_custom_match_id,
+                enabled: player.enabled,
+                filters: player.filters.clone(),
             })
         })
         .collect();
@@ -84,30 +196,355 @@ async fn get_new_games(client: &Client) -> Vec<(MatchesResponse, bool)> {
     future::join_all(new_games)
         .await
         .into_iter()
-        .filter_map(std::convert::identity)
+        .flatten()
         .collect()
 }
 
-fn should_keep_match(data: &Data) -> bool {
-    data.details.playlist.properties.ranked && data.player.outcome != Outcome::Left
+/// Pages back through a player's matches of one mode, newest first, until
+/// reaching `last_match_id` or `max_catch_up` matches.
+///
+/// A player with no stored match only gets their latest match, so
+/// registering doesn't replay their whole history.
+async fn get_unseen_matches(
+    api: &dyn HaloApi,
+    gamertag: &str,
+    mode: Mode,
+    last_match_id: Option<&str>,
+    max_catch_up: usize,
+) -> Result<MatchesResponse> {
+    let last_match_id = match last_match_id {
+        Some(last_match_id) => last_match_id,
+        None => return api.get_matches(gamertag, mode, 1, 0).await,
+    };
+
+    let mut unseen: Option<MatchesResponse> = None;
+    let mut offset = 0;
+
+    loop {
+        let count = PAGE_SIZE.min(max_catch_up - offset);
+        let mut page = api.get_matches(gamertag, mode, count, offset).await?;
+        let page_len = page.data.len();
+
+        let caught_up = match page.data.iter().position(|d| d.id == last_match_id) {
+            Some(seen) => {
+                page.data.truncate(seen);
+                true
+            }
+            None => false,
+        };
+
+        offset += page_len;
+
+        match &mut unseen {
+            Some(unseen) => unseen.data.append(&mut page.data),
+            None => unseen = Some(page),
+        }
+
+        if caught_up || page_len < count {
+            break;
+        }
+        if offset >= max_catch_up {
+            println!(
+                "{} has more than {} unseen matches, skipping the rest",
+                gamertag, max_catch_up
+            );
+            break;
+        }
+    }
+
+    Ok(unseen.expect("at least one page"))
 }
 
-async fn get_latest_match(gamertag: &str) -> Result<MatchesResponse, Box<dyn Error>> {
-    let request = MatchesRequest {
-        gamertag: gamertag,
-        limit: Limit { count: 1 },
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halo_api::FixtureHaloApi;
+    use std::fs;
+
+    /// `count` matches for `gamertag`, newest first, with IDs like
+    /// `{prefix}-0`, `{prefix}-1`, ... copied from a recorded match. Custom
+    /// games have no playlist.
+    fn history(gamertag: &str, prefix: &str, count: usize) -> MatchesResponse {
+        let recorded: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string("fixtures/matches/billzabob.json").unwrap())
+                .unwrap();
+        let played_at = chrono::DateTime::parse_from_rfc3339("2022-02-12T12:00:00Z").unwrap();
+        let data: Vec<serde_json::Value> = (0..count)
+            .map(|i| {
+                let mut data = recorded["data"][0].clone();
+                data["id"] = format!("{}-{}", prefix, i).into();
+                if prefix == "custom" {
+                    data["details"]["playlist"] = serde_json::Value::Null;
+                }
+                data["played_at"] = (played_at - chrono::Duration::hours(i as i64))
+                    .to_rfc3339()
+                    .into();
+                data
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "data": data,
+            "additional": { "gamertag": gamertag },
+        }))
+        .unwrap()
+    }
+
+    fn api(histories: &[(&str, usize)]) -> FixtureHaloApi {
+        let mut api = FixtureHaloApi::default();
+        for (gamertag, count) in histories {
+            api.record_matches(Mode::Matchmade, &history(gamertag, "match", *count));
+            api.record_matches(Mode::Custom, &history(gamertag, "custom", 0));
+        }
+        api
+    }
+
+    fn player(gamertag: &str, latest_match_id: Option<&str>) -> TrackedPlayer {
+        TrackedPlayer {
+            gamertag: gamertag.to_owned(),
+            latest_match_id: latest_match_id.map(str::to_owned),
+            latest_custom_match_id: None,
+            enabled: true,
+            filters: Filters::default(),
+        }
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.poller.interval_secs = 0;
+        config
+    }
+
+    /// Keeps players and queued matches in memory, like the database would.
+    #[derive(Default)]
+    struct MemoryStore {
+        players: Vec<TrackedPlayer>,
+        /// Every queued match, posted or not.
+        stored: Vec<(String, String)>,
+        /// The queued matches that get posted.
+        queued: Vec<(String, String)>,
+    }
+
+    impl MemoryStore {
+        fn new(players: Vec<TrackedPlayer>) -> MemoryStore {
+            MemoryStore {
+                players,
+                ..Default::default()
+            }
+        }
+
+        fn queued_ids(&self) -> Vec<&str> {
+            self.queued.iter().map(|(_, id)| id.as_str()).collect()
+        }
+
+        fn player(&self, gamertag: &str) -> &TrackedPlayer {
+            self.players
+                .iter()
+                .find(|player| player.gamertag == gamertag)
+                .unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl PlayerStore for MemoryStore {
+        async fn players(&mut self) -> Result<Vec<TrackedPlayer>> {
+            Ok(self.players.clone())
+        }
+
+        async fn queue(&mut self, games: &NewGames, _grace: Duration) -> Result<()> {
+            let gamertag = &games.response.additional.gamertag;
+            for data in &games.response.data {
+                self.stored.push((gamertag.clone(), data.id.clone()));
+                if games.posts(data) {
+                    self.queued.push((gamertag.clone(), data.id.clone()));
+                }
+            }
+            let player = self
+                .players
+                .iter_mut()
+                .find(|player| player.gamertag.eq_ignore_ascii_case(gamertag))
+                .unwrap();
+            if let Some(id) = &games.latest_match_id {
+                player.latest_match_id = Some(id.clone());
+            }
+            if let Some(id) = &games.latest_custom_match_id {
+                player.latest_custom_match_id = Some(id.clone());
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn polls_queue_unseen_matches_oldest_first() {
+        let api = api(&[("Alpha", 10)]);
+        let mut store = MemoryStore::new(vec![player("Alpha", Some("match-3"))]);
+
+        poll(&mut store, &api, &config()).await.unwrap();
+
+        assert_eq!(store.queued_ids(), ["match-2", "match-1", "match-0"]);
+        assert_eq!(
+            store.player("Alpha").latest_match_id.as_deref(),
+            Some("match-0")
+        );
+
+        poll(&mut store, &api, &config()).await.unwrap();
+        assert_eq!(store.queued.len(), 3, "nothing new the second time");
+    }
+
+    #[tokio::test]
+    async fn new_players_only_get_their_latest_match() {
+        let api = api(&[("Alpha", 10)]);
+        let mut store = MemoryStore::new(vec![player("Alpha", None)]);
+
+        poll(&mut store, &api, &config()).await.unwrap();
+
+        assert_eq!(store.queued_ids(), ["match-0"]);
+    }
+
+    #[tokio::test]
+    async fn a_cursor_missing_from_the_history_catches_up_at_most_max_catch_up() {
+        let api = api(&[("Alpha", PAGE_SIZE * 3)]);
+        let mut store = MemoryStore::new(vec![player("Alpha", Some("someone-elses-match"))]);
+        let mut config = config();
+        config.poller.max_catch_up = 10;
+
+        poll(&mut store, &api, &config).await.unwrap();
+
+        assert_eq!(store.queued.len(), 10);
+        assert_eq!(
+            store.player("Alpha").latest_match_id.as_deref(),
+            Some("match-0")
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_players_have_matches_stored_but_not_posted() {
+        let api = api(&[("Alpha", 10)]);
+        let mut alpha = player("Alpha", Some("match-3"));
+        alpha.enabled = false;
+        let mut store = MemoryStore::new(vec![alpha]);
+
+        poll(&mut store, &api, &config()).await.unwrap();
 
-    let token = std::env::var("HALO_API_TOKEN")?;
+        assert!(store.queued.is_empty());
+        assert_eq!(store.stored.len(), 3);
+        assert_eq!(
+            store.player("Alpha").latest_match_id.as_deref(),
+            Some("match-0")
+        );
+    }
+
+    #[tokio::test]
+    async fn one_failing_player_doesnt_hold_up_the_rest() {
+        let api = api(&[("Alpha", 10)]);
+        let mut store = MemoryStore::new(vec![
+            player("Ghost", Some("match-3")),
+            player("Alpha", Some("match-1")),
+        ]);
+
+        poll(&mut store, &api, &config()).await.unwrap();
+
+        assert_eq!(store.queued_ids(), ["match-0"]);
+        assert_eq!(
+            store.player("Ghost").latest_match_id.as_deref(),
+            Some("match-3")
+        );
+    }
+
+    #[tokio::test]
+    async fn filtered_out_matches_are_stored_but_not_posted() {
+        let mut api = api(&[("Alpha", 3)]);
+        api.record_matches(Mode::Custom, &history("Alpha", "custom", 2));
+        let mut alpha = player("Alpha", Some("match-2"));
+        alpha.latest_custom_match_id = Some("custom-1".to_owned());
+        let mut store = MemoryStore::new(vec![alpha]);
+
+        poll(&mut store, &api, &config()).await.unwrap();
+
+        assert_eq!(store.queued_ids(), ["match-1", "match-0"]);
+        let mut stored: Vec<&str> = store.stored.iter().map(|(_, id)| id.as_str()).collect();
+        stored.sort_unstable();
+        assert_eq!(stored, ["custom-0", "match-0", "match-1"]);
+        assert_eq!(
+            store.player("Alpha").latest_custom_match_id.as_deref(),
+            Some("custom-0")
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_games_are_tracked_separately() {
+        let mut api = api(&[("Alpha", 3)]);
+        api.record_matches(Mode::Custom, &history("Alpha", "custom", 3));
+        let mut alpha = player("Alpha", Some("match-0"));
+        alpha.filters = Filters::all();
+        alpha.latest_custom_match_id = Some("custom-2".to_owned());
+        let mut store = MemoryStore::new(vec![alpha]);
+
+        poll(&mut store, &api, &config()).await.unwrap();
+
+        assert_eq!(store.queued_ids(), ["custom-1", "custom-0"]);
+        let alpha = store.player("Alpha");
+        assert_eq!(alpha.latest_match_id.as_deref(), Some("match-0"));
+        assert_eq!(alpha.latest_custom_match_id.as_deref(), Some("custom-0"));
+    }
+
+    async fn unseen_ids(
+        api: &FixtureHaloApi,
+        last_match_id: &str,
+        max_catch_up: usize,
+    ) -> Vec<String> {
+        get_unseen_matches(
+            api,
+            "Alpha",
+            Mode::Matchmade,
+            Some(last_match_id),
+            max_catch_up,
+        )
+        .await
+        .unwrap()
+        .data
+        .into_iter()
+        .map(|data| data.id)
+        .collect()
+    }
+
+    fn ids(range: std::ops::Range<usize>) -> Vec<String> {
+        range.map(|i| format!("match-{}", i)).collect()
+    }
+
+    #[tokio::test]
+    async fn unseen_matches_stop_at_the_cursor_on_the_first_page() {
+        let api = api(&[("Alpha", PAGE_SIZE * 3)]);
+
+        assert_eq!(unseen_ids(&api, "match-5", 100).await, ids(0..5));
+        assert!(unseen_ids(&api, "match-0", 100).await.is_empty());
+    }
 
-    let response = reqwest::Client::new()
-        .post("https://halo.api.stdlib.com/infinite@0.3.8/stats/matches/list/")
-        .bearer_auth(token)
-        .json(&request)
-        .send()
-        .await?
-        .json()
-        .await?;
+    #[tokio::test]
+    async fn unseen_matches_page_back_to_the_cursor() {
+        let api = api(&[("Alpha", PAGE_SIZE * 3)]);
 
-    Ok(response)
+        let cursor = format!("match-{}", PAGE_SIZE * 2 + 3);
+        assert_eq!(
+            unseen_ids(&api, &cursor, 100).await,
+            ids(0..PAGE_SIZE * 2 + 3)
+        );
+    }
+
+    #[tokio::test]
+    async fn unseen_matches_stop_at_max_catch_up() {
+        let api = api(&[("Alpha", PAGE_SIZE * 3)]);
+
+        assert_eq!(unseen_ids(&api, "missing", 30).await, ids(0..30));
+        assert_eq!(unseen_ids(&api, "missing", 10).await, ids(0..10));
+    }
+
+    #[tokio::test]
+    async fn unseen_matches_stop_at_a_short_final_page() {
+        let api = api(&[("Alpha", PAGE_SIZE + 7)]);
+
+        assert_eq!(
+            unseen_ids(&api, "missing", 100).await,
+            ids(0..PAGE_SIZE + 7)
+        );
+    }
 }
@@ -0,0 +1,147 @@
+use crate::error::Result;
+use crate::matches_response::Data;
+use chrono::{DateTime, Utc};
+use serenity::model::id::ChannelId;
+use std::time::Duration;
+use tokio_postgres::{Client, Transaction};
+
+/// How many times posting a match is tried before it's dropped.
+const MAX_ATTEMPTS: i32 = 5;
+
+/// How many minutes to wait before retrying after the first failed attempt.
+/// Each later failure doubles it.
+const RETRY_BASE_MINS: i64 = 1;
+
+/// A match waiting to be stored and posted, with every registered player
+/// seen in it.
+pub struct QueuedMatch {
+    pub match_id: String,
+    pub attempts: i32,
+    /// Channels it's already been posted in.
+    pub posted_channels: Vec<ChannelId>,
+    /// Players whose filters keep the match, so it's posted for them.
+    pub players: Vec<(String, Data)>,
+    /// Players who don't want it posted, so it's only stored for them.
+    pub unposted: Vec<(String, Data)>,
+}
+
+impl QueuedMatch {
+    /// Every player in the match, posted or not.
+    pub fn all_players(&self) -> impl Iterator<Item = &(String, Data)> {
+        self.players.iter().chain(&self.unposted)
+    }
+}
+
+/// Queues a player's match to be stored, and posted if `post` is set, once
+/// `grace` has passed, so other registered players in it can be posted along
+/// with them.
+pub async fn push(
+    transaction: &Transaction<'_>,
+    gamertag: &str,
+    data: &Data,
+    post: bool,
+    grace: Duration,
+) -> Result<()> {
+    let grace = chrono::Duration::from_std(grace).unwrap_or_else(|_| chrono::Duration::zero());
+    transaction
+        .execute(
+            "insert into pending_matches (match_id, played_at, ready_at) values ($1, $2, $3) \
+             on conflict do nothing",
+            &[&data.id, &data.played_at, &(Utc::now() + grace)],
+        )
+        .await?;
+    transaction
+        .execute(
+            "insert into pending_match_players (match_id, gamertag, data, post) \
+             values ($1, $2, $3, $4) on conflict do nothing",
+            &[&data.id, &gamertag, &serde_json::to_string(data)?, &post],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Every queued match that's due to be posted, oldest first.
+pub async fn ready(client: &Client) -> Result<Vec<QueuedMatch>> {
+    let rows = client
+        .query(
+            "select p.match_id, p.attempts, p.posted_channels, mp.gamertag, mp.data, mp.post \
+             from pending_matches p \
+             join pending_match_players mp on mp.match_id = p.match_id \
+             where p.ready_at <= now() \
+             order by p.played_at, p.match_id, mp.gamertag",
+            &[],
+        )
+        .await?;
+
+    let mut ready: Vec<QueuedMatch> = Vec::new();
+    for row in rows {
+        let match_id: String = row.get(0);
+        let player = (row.get(3), serde_json::from_str(row.get(4))?);
+        if ready
+            .last()
+            .is_none_or(|queued| queued.match_id != match_id)
+        {
+            ready.push(QueuedMatch {
+                match_id,
+                attempts: row.get(1),
+                posted_channels: row
+                    .get::<_, Vec<i64>>(2)
+                    .into_iter()
+                    .map(|channel_id| ChannelId(channel_id as u64))
+                    .collect(),
+                players: Vec::new(),
+                unposted: Vec::new(),
+            });
+        }
+        let queued = ready.last_mut().expect("just pushed");
+        if row.get(5) {
+            queued.players.push(player);
+        } else {
+            queued.unposted.push(player);
+        }
+    }
+    Ok(ready)
+}
+
+/// Records that a match has been posted in a channel.
+pub async fn posted_in(client: &Client, match_id: &str, channel_id: ChannelId) -> Result<()> {
+    client
+        .execute(
+            "update pending_matches set posted_channels = array_append(posted_channels, $2) \
+             where match_id = $1",
+            &[&match_id, &(channel_id.0 as i64)],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Removes a match that's been stored and posted everywhere.
+pub async fn done(client: &Client, match_id: &str) -> Result<()> {
+    client
+        .execute(
+            "delete from pending_matches where match_id = $1",
+            &[&match_id],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Schedules another attempt at posting a match, or drops it after
+/// `MAX_ATTEMPTS`. Returns whether it was dropped.
+pub async fn failed(client: &Client, queued: &QueuedMatch) -> Result<bool> {
+    let attempts = queued.attempts + 1;
+    if attempts >= MAX_ATTEMPTS {
+        done(client, &queued.match_id).await?;
+        return Ok(true);
+    }
+
+    let retry_at: DateTime<Utc> =
+        Utc::now() + chrono::Duration::minutes(RETRY_BASE_MINS << (attempts - 1));
+    client
+        .execute(
+            "update pending_matches set attempts = $2, ready_at = $3 where match_id = $1",
+            &[&queued.match_id, &attempts, &retry_at],
+        )
+        .await?;
+    Ok(false)
+}
@@ -1,3 +1,4 @@
+use crate::matches_response::{Outcome, Progression};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
@@ -13,7 +14,22 @@ pub struct Data {
 
 #[derive(Debug, Deserialize)]
 pub struct Player {
+    pub details: PlayerDetails,
+    pub team: PlayerTeam,
     pub stats: Stats,
+    pub rank: usize,
+    /// Only present in ranked matches.
+    pub progression: Option<Progression>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlayerDetails {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlayerTeam {
+    pub id: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -25,6 +41,8 @@ pub struct Teams {
 pub struct TeamDetail {
     pub team: Team,
     pub stats: Stats,
+    pub rank: usize,
+    pub outcome: Outcome,
 }
 
 #[derive(Debug, Deserialize)]
@@ -36,6 +54,12 @@ pub struct Stats {
 pub struct CoreStats {
     pub damage: Damage,
     pub summary: Summary,
+    pub shots: Shots,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Shots {
+    pub accuracy: f64,
 }
 
 #[derive(Debug, Deserialize)]
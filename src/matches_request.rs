@@ -4,9 +4,19 @@ use serde::Serialize;
 pub struct MatchesRequest<'a> {
     pub gamertag: &'a str,
     pub limit: Limit,
+    pub mode: Mode,
+}
+
+/// Matchmade and custom games are listed separately.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Matchmade,
+    Custom,
 }
 
 #[derive(Serialize)]
 pub struct Limit {
     pub count: usize,
+    pub offset: usize,
 }
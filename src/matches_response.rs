@@ -1,13 +1,14 @@
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct MatchesResponse {
     pub data: Vec<Data>,
     pub additional: Additional,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Data {
     pub id: String,
     pub details: Details,
@@ -16,43 +17,54 @@ pub struct Data {
     pub duration: Duration,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Duration {
     pub seconds: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Additional {
     pub gamertag: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Details {
     pub category: Category,
     pub map: GameMap,
-    pub playlist: Playlist,
+    /// Missing for custom games.
+    pub playlist: Option<Playlist>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Playlist {
+    pub name: String,
     pub properties: Properties,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Properties {
     pub queue: Option<Queue>,
     pub input: Option<Input>,
     pub ranked: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Queue {
     SoloDuo,
     Open,
 }
 
-#[derive(Debug, Deserialize)]
+impl Queue {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Queue::SoloDuo => "solo-duo",
+            Queue::Open => "open",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Input {
     Controller,
@@ -60,23 +72,33 @@ pub enum Input {
     Crossplay,
 }
 
-#[derive(Debug, Deserialize)]
+impl Input {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Input::Controller => "controller",
+            Input::Mnk => "mnk",
+            Input::Crossplay => "crossplay",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Category {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GameMap {
     pub name: String,
     pub asset: MapAsset,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct MapAsset {
     pub thumbnail_url: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Player {
     pub stats: Stats,
     pub rank: usize,
@@ -85,23 +107,23 @@ pub struct Player {
     pub team: Team,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Team {
     pub id: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Progression {
     pub csr: Csr,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Csr {
     pub pre_match: CsrResult,
     pub post_match: CsrResult,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct CsrResult {
     pub tier: Tier,
     pub value: isize,
@@ -110,7 +132,8 @@ pub struct CsrResult {
     pub tier_image_url: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// Declared lowest to highest, so tiers compare by rank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub enum Tier {
     Unranked,
     Bronze,
@@ -121,7 +144,48 @@ pub enum Tier {
     Onyx,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+impl Tier {
+    pub const ALL: [Tier; 7] = [
+        Tier::Unranked,
+        Tier::Bronze,
+        Tier::Silver,
+        Tier::Gold,
+        Tier::Platinum,
+        Tier::Diamond,
+        Tier::Onyx,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tier::Unranked => "Unranked",
+            Tier::Bronze => "Bronze",
+            Tier::Silver => "Silver",
+            Tier::Gold => "Gold",
+            Tier::Platinum => "Platinum",
+            Tier::Diamond => "Diamond",
+            Tier::Onyx => "Onyx",
+        }
+    }
+}
+
+impl FromStr for Tier {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Unranked" => Ok(Tier::Unranked),
+            "Bronze" => Ok(Tier::Bronze),
+            "Silver" => Ok(Tier::Silver),
+            "Gold" => Ok(Tier::Gold),
+            "Platinum" => Ok(Tier::Platinum),
+            "Diamond" => Ok(Tier::Diamond),
+            "Onyx" => Ok(Tier::Onyx),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Outcome {
     Win,
@@ -130,12 +194,23 @@ pub enum Outcome {
     Left,
 }
 
-#[derive(Debug, Deserialize)]
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Win => "win",
+            Outcome::Loss => "loss",
+            Outcome::Draw => "draw",
+            Outcome::Left => "left",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Stats {
     pub core: CoreStats,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct CoreStats {
     pub summary: Summary,
     pub damage: Damage,
@@ -144,31 +219,31 @@ pub struct CoreStats {
     pub kda: f64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Breakdowns {
     pub medals: Vec<Medal>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Medal {
     pub name: String,
     pub count: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Summary {
     pub kills: usize,
     pub deaths: usize,
     pub assists: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Damage {
     pub taken: usize,
     pub dealt: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Shots {
     pub accuracy: f64,
 }
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+use tokio_postgres::Client;
+
+struct Migration {
+    version: i32,
+    name: &'static str,
+    sql: &'static str,
+}
+
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../migrations/", $name, ".sql")),
+        }
+    };
+}
+
+/// Every migration in the order it is applied. Append new ones to the end;
+/// never edit or reorder a migration that has shipped.
+const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_create_users"),
+    migration!(2, "0002_create_guilds"),
+    migration!(3, "0003_create_match_history"),
+    migration!(4, "0004_add_display_gamertag"),
+    migration!(5, "0005_create_user_settings"),
+    migration!(6, "0006_create_rank_roles"),
+    migration!(7, "0007_add_session_summaries"),
+    migration!(8, "0008_add_guild_recaps"),
+    migration!(9, "0009_create_embed_templates"),
+    migration!(10, "0010_backfill_original_guild"),
+    migration!(11, "0011_create_pending_matches"),
+    migration!(12, "0012_add_pending_match_post"),
+];
+
+/// Arbitrary key for the advisory lock that stops two bots starting against
+/// the same database from migrating it at the same time.
+const MIGRATION_LOCK: i64 = 0x636f7274616e61;
+
+/// Applies every migration not yet recorded in `schema_migrations`, all in
+/// one transaction.
+pub async fn run(client: &mut Client) -> Result<(), tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    transaction
+        .execute("select pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    transaction
+        .batch_execute(
+            "create table if not exists schema_migrations (
+                version integer primary key,
+                name text not null,
+                applied_at timestamptz not null default now()
+            )",
+        )
+        .await?;
+
+    let applied: HashSet<i32> = transaction
+        .query("select version from schema_migrations", &[])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    for migration in MIGRATIONS {
+        if applied.contains(&migration.version) {
+            continue;
+        }
+
+        println!("Applying migration {}", migration.name);
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                "insert into schema_migrations (version, name) values ($1, $2)",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+    }
+
+    transaction.commit().await
+}
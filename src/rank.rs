@@ -0,0 +1,51 @@
+use crate::matches_response::Tier::{self, *};
+use tokio_postgres::Row;
+
+pub fn rank_emoji(tier: &Tier) -> &'static str {
+    match tier {
+        Unranked => "<:Unranked_Rank_Icon:938636507751800892>",
+        Bronze => "<:Bronze_Rank_Icon:933098600471363624>",
+        Silver => "<:Silver_Rank_Icon:933098600609775646>",
+        Gold => "<:Gold_Rank_Icon:933098600437776465>",
+        Platinum => "<:Platinum_Rank_Icon:933098600718802954>",
+        Diamond => "<:Diamond_Rank_Icon:933098600488116294>",
+        Onyx => "<:Onyx_Rank_Icon:933098600332931143>",
+    }
+}
+
+/// The tier and division for a CSR, e.g. "Platinum 3". Onyx has no
+/// divisions.
+pub fn rank_name(tier: &Tier, sub_tier: usize) -> String {
+    match tier {
+        Onyx => "Onyx".to_owned(),
+        _ => format!("{} {}", tier.as_str(), sub_tier.saturating_sub(1)),
+    }
+}
+
+/// The rank icon followed by `rank_name`.
+pub fn rank_label(tier: &Tier, sub_tier: usize) -> String {
+    format!("{} {}", rank_emoji(tier), rank_name(tier, sub_tier))
+}
+
+/// A rank as stored with a match.
+#[derive(Clone, Copy)]
+pub struct StoredRank {
+    pub tier: Tier,
+    pub sub_tier: usize,
+    pub value: i32,
+}
+
+impl StoredRank {
+    /// Reads the tier, sub tier and CSR columns starting at `first`.
+    pub fn from_row(row: &Row, first: usize) -> Option<StoredRank> {
+        Some(StoredRank {
+            tier: row.get::<_, Option<&str>>(first)?.parse().ok()?,
+            sub_tier: row.get::<_, Option<i32>>(first + 1)? as usize,
+            value: row.get::<_, Option<i32>>(first + 2)?,
+        })
+    }
+
+    pub fn label(&self) -> String {
+        format!("{} ({})", rank_label(&self.tier, self.sub_tier), self.value)
+    }
+}
@@ -0,0 +1,78 @@
+use crate::error::Result;
+use crate::matches_response::{Csr, Tier};
+use crate::rank;
+use serenity::http::Http;
+use serenity::model::channel::Message;
+use serenity::model::id::ChannelId;
+
+/// A match that moved a player across a tier boundary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RankChange {
+    /// Finished placements and got their first tier.
+    Placed,
+    Up,
+    Down,
+}
+
+impl RankChange {
+    /// Only tier changes count; moving between divisions of a tier doesn't.
+    pub fn from_csr(csr: &Csr) -> Option<RankChange> {
+        let pre = csr.pre_match.tier;
+        let post = csr.post_match.tier;
+        if pre == post {
+            None
+        } else if pre == Tier::Unranked {
+            Some(RankChange::Placed)
+        } else if post > pre {
+            Some(RankChange::Up)
+        } else {
+            Some(RankChange::Down)
+        }
+    }
+}
+
+pub async fn announce_rank_change(
+    http: &Http,
+    channel_id: ChannelId,
+    gamertag: &str,
+    csr: &Csr,
+    change: RankChange,
+) -> Result<Message> {
+    let pre = &csr.pre_match;
+    let post = &csr.post_match;
+    let new_rank = rank::rank_name(&post.tier, post.sub_tier);
+
+    let (title, color) = match change {
+        RankChange::Placed => (
+            format!("🎖️ {} placed into {}!", gamertag, new_rank),
+            (255, 215, 0),
+        ),
+        RankChange::Up => (
+            format!("🎉 {} ranked up to {}!", gamertag, new_rank),
+            (255, 215, 0),
+        ),
+        RankChange::Down => (
+            format!("😔 {} dropped to {}", gamertag, new_rank),
+            (255, 0, 0),
+        ),
+    };
+
+    let message = channel_id
+        .send_message(http, |m| {
+            m.embed(|e| {
+                e.title(title)
+                    .color(color)
+                    .description(format!(
+                        "{} ({}) → {} ({})",
+                        rank::rank_label(&pre.tier, pre.sub_tier),
+                        pre.value,
+                        rank::rank_label(&post.tier, post.sub_tier),
+                        post.value
+                    ))
+                    .thumbnail(&post.tier_image_url)
+            })
+        })
+        .await?;
+
+    Ok(message)
+}
@@ -0,0 +1,318 @@
+use crate::config::Config;
+use crate::error::Result;
+use crate::stats;
+use chrono::{DateTime, Datelike, Duration, Utc};
+use serenity::builder::CreateEmbed;
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, GuildId};
+use std::sync::Arc;
+use tokio::time::{self, MissedTickBehavior};
+use tokio_postgres::Client;
+
+/// How often to check whether a recap is due.
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Every stored line of a guild's registered players between `$2` and `$3`,
+/// for the recap queries to build on.
+const WEEK: &str = "with week as ( \
+         select coalesce(u.display_gamertag, u.gamertag) as player, mp.*, \
+         m.played_at, m.ranked, m.map_name, m.category \
+         from guild_users gu \
+         join users u on u.discord_id = gu.discord_id \
+         join match_players mp on mp.gamertag = u.gamertag \
+         join matches m on m.id = mp.match_id \
+         where gu.guild_id = $1 and m.played_at >= $2 and m.played_at < $3 \
+     )";
+
+/// The most recent recap time at or before `now`: Monday at `hour` UTC.
+fn recap_time(now: DateTime<Utc>, hour: u32) -> DateTime<Utc> {
+    let days_since_monday = now.weekday().num_days_from_monday() as i64;
+    let monday = (now.date() - Duration::days(days_since_monday)).and_hms(hour, 0, 0);
+    if monday > now {
+        monday - Duration::weeks(1)
+    } else {
+        monday
+    }
+}
+
+/// Builds the recap for the week before `end`, or `None` if nobody played.
+async fn build_recap(
+    client: &Client,
+    config: &Config,
+    guild_id: GuildId,
+    end: DateTime<Utc>,
+) -> Result<Option<CreateEmbed>> {
+    let start = end - Duration::weeks(1);
+    let guild = guild_id.0 as i64;
+    let params: [&(dyn tokio_postgres::types::ToSql + Sync); 3] = [&guild, &start, &end];
+
+    let most_games = client
+        .query_opt(
+            format!(
+                "{} select player, count(*) from week group by player \
+                 order by 2 desc, player limit 1",
+                WEEK
+            )
+            .as_str(),
+            &params,
+        )
+        .await?;
+    let most_games = match most_games {
+        Some(row) => format!("{} ({} games)", row.get::<_, &str>(0), row.get::<_, i64>(1)),
+        None => return Ok(None),
+    };
+
+    let csr_changes = client
+        .query(
+            format!(
+                "{} select player, sum(post_csr - pre_csr) as change from week \
+                 where ranked and post_csr is not null group by player",
+                WEEK
+            )
+            .as_str(),
+            &params,
+        )
+        .await?;
+    let csr_changes: Vec<(&str, i64)> = csr_changes
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+    let csr_line = |change: Option<&(&str, i64)>| match change {
+        Some((player, change)) => format!("{} ({})", player, stats::format_change(*change)),
+        None => "Nobody".to_owned(),
+    };
+    let gainer = csr_line(
+        csr_changes
+            .iter()
+            .filter(|(_, change)| *change > 0)
+            .max_by_key(|(_, change)| *change),
+    );
+    let loser = csr_line(
+        csr_changes
+            .iter()
+            .filter(|(_, change)| *change < 0)
+            .min_by_key(|(_, change)| *change),
+    );
+
+    let top_damage = client
+        .query_opt(
+            format!(
+                "{} select player, damage_dealt, map_name, category from week \
+                 order by damage_dealt desc, player limit 1",
+                WEEK
+            )
+            .as_str(),
+            &params,
+        )
+        .await?
+        .map(|row| {
+            format!(
+                "{} ({} on {}, {})",
+                row.get::<_, &str>(0),
+                row.get::<_, i32>(1),
+                row.get::<_, &str>(2),
+                row.get::<_, &str>(3)
+            )
+        })
+        .unwrap_or_else(|| "Nobody".to_owned());
+
+    let most_medals = client
+        .query_opt(
+            format!(
+                "{} select w.player, sum(mpm.count) from week w \
+                 join match_player_medals mpm \
+                 on mpm.match_id = w.match_id and mpm.gamertag = w.gamertag \
+                 group by w.player order by 2 desc, w.player limit 1",
+                WEEK
+            )
+            .as_str(),
+            &params,
+        )
+        .await?
+        .map(|row| format!("{} ({})", row.get::<_, &str>(0), row.get::<_, i64>(1)))
+        .unwrap_or_else(|| "Nobody".to_owned());
+
+    // Rarity is judged against every medal the bot has ever stored, not just
+    // this guild's or this week's.
+    let rarest_medal = client
+        .query_opt(
+            format!(
+                "{} , totals as ( \
+                     select name, sum(count) as total from match_player_medals group by name \
+                 ) \
+                 select w.player, mpm.name, t.total from week w \
+                 join match_player_medals mpm \
+                 on mpm.match_id = w.match_id and mpm.gamertag = w.gamertag \
+                 join totals t on t.name = mpm.name \
+                 order by t.total, w.played_at limit 1",
+                WEEK
+            )
+            .as_str(),
+            &params,
+        )
+        .await?
+        .map(|row| {
+            let name: &str = row.get(1);
+            format!(
+                "{} {} by {}",
+                crate::match_card::name_to_emoji(name).unwrap_or(""),
+                name,
+                row.get::<_, &str>(0)
+            )
+        })
+        .unwrap_or_else(|| "None".to_owned());
+
+    let best_win_rate = client
+        .query_opt(
+            format!(
+                "{} select player, count(*), \
+                 100.0 * count(*) filter (where outcome = 'win') / count(*)::float8 as win_rate \
+                 from week group by player having count(*) >= $4 \
+                 order by 3 desc, 2 desc, player limit 1",
+                WEEK
+            )
+            .as_str(),
+            &[&guild, &start, &end, &config.recap.min_games],
+        )
+        .await?
+        .map(|row| {
+            format!(
+                "{} ({:.0}% over {} games)",
+                row.get::<_, &str>(0),
+                row.get::<_, f64>(2),
+                row.get::<_, i64>(1)
+            )
+        })
+        .unwrap_or_else(|| "Nobody played enough".to_owned());
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!(
+            "Weekly recap: {} – {}",
+            start.format("%b %-d"),
+            (end - Duration::days(1)).format("%b %-d")
+        ))
+        .color((0, 0, 255))
+        .field("Most games", most_games, true)
+        .field("Biggest CSR gain", gainer, true)
+        .field("Biggest CSR loss", loser, true)
+        .field("Highest damage game", top_damage, true)
+        .field("Most medals", most_medals, true)
+        .field("Rarest medal", rarest_medal, true)
+        .field(
+            format!("Best win rate ({}+ games)", config.recap.min_games),
+            best_win_rate,
+            true,
+        )
+        .timestamp(&end);
+
+    Ok(Some(embed))
+}
+
+async fn post_due_recaps(http: &Http, client: &Client, config: &Config) -> Result<()> {
+    let end = recap_time(Utc::now(), config.recap.hour_utc);
+
+    // Servers that have never had a recap start from the next one, rather
+    // than getting a week from before the bot was posting there.
+    client
+        .execute(
+            "update guilds set last_recap_at = $1 where last_recap_at is null",
+            &[&end],
+        )
+        .await?;
+
+    let guilds = client
+        .query(
+            "select guild_id, channel_id from guilds where last_recap_at < $1",
+            &[&end],
+        )
+        .await?;
+
+    for row in guilds {
+        let guild_id = GuildId(row.get::<_, i64>(0) as u64);
+        let channel_id = ChannelId(row.get::<_, i64>(1) as u64);
+
+        match build_recap(client, config, guild_id, end).await {
+            Ok(Some(embed)) => {
+                let result = channel_id
+                    .send_message(http, |m| {
+                        m.embed(|e| {
+                            *e = embed;
+                            e
+                        })
+                    })
+                    .await;
+                if let Err(why) = result {
+                    println!("Failed sending weekly recap to {}: {}", channel_id, why);
+                    continue;
+                }
+            }
+            Ok(None) => {}
+            Err(why) => {
+                println!("Failed building weekly recap for {}: {}", guild_id, why);
+                continue;
+            }
+        }
+
+        let result = client
+            .execute(
+                "update guilds set last_recap_at = $2 where guild_id = $1",
+                &[&(guild_id.0 as i64), &end],
+            )
+            .await;
+        if let Err(why) = result {
+            println!("Failed recording weekly recap for {}: {}", guild_id, why);
+        }
+    }
+
+    Ok(())
+}
+
+/// Posts each guild's recap of the previous week on Mondays at
+/// `recap.hour_utc`, or as soon as the bot is back if it was down then.
+pub async fn post_weekly_recaps(client: Arc<Client>, http: Arc<Http>, config: Arc<Config>) {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(why) = post_due_recaps(&http, &client, &config).await {
+            println!("Failed posting weekly recaps: {}", why);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn mondays_before_the_hour_go_back_a_week() {
+        assert_eq!(
+            recap_time(Utc.ymd(2022, 2, 14).and_hms(16, 59, 0), 17),
+            Utc.ymd(2022, 2, 7).and_hms(17, 0, 0)
+        );
+    }
+
+    #[test]
+    fn mondays_from_the_hour_use_that_day() {
+        assert_eq!(
+            recap_time(Utc.ymd(2022, 2, 14).and_hms(17, 0, 0), 17),
+            Utc.ymd(2022, 2, 14).and_hms(17, 0, 0)
+        );
+        assert_eq!(
+            recap_time(Utc.ymd(2022, 2, 14).and_hms(23, 30, 0), 17),
+            Utc.ymd(2022, 2, 14).and_hms(17, 0, 0)
+        );
+    }
+
+    #[test]
+    fn sundays_use_the_monday_before() {
+        assert_eq!(
+            recap_time(Utc.ymd(2022, 2, 20).and_hms(12, 0, 0), 17),
+            Utc.ymd(2022, 2, 14).and_hms(17, 0, 0)
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use crate::commands::{self, Reply};
+use crate::config::Config;
+use crate::error::Result;
+use crate::matches_response::Tier;
+use crate::rank;
+use serenity::builder::CreateApplicationCommandOption;
+use serenity::http::Http;
+use serenity::model::id::{GuildId, RoleId, UserId};
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+    ApplicationCommandOptionType,
+};
+use serenity::model::misc::Mentionable;
+use std::sync::Arc;
+use tokio::time::{self, MissedTickBehavior};
+use tokio_postgres::Client;
+
+/// The role each tier maps to in a guild.
+async fn rank_roles(client: &Client, guild_id: GuildId) -> Result<Vec<(Tier, RoleId)>> {
+    let rows = client
+        .query(
+            "select tier, role_id from rank_roles where guild_id = $1",
+            &[&(guild_id.0 as i64)],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            let tier = row.get::<_, &str>(0).parse().ok()?;
+            Some((tier, RoleId(row.get::<_, i64>(1) as u64)))
+        })
+        .collect())
+}
+
+/// Gives a member the role mapped to `tier` and takes away the roles mapped
+/// to every other tier. Does nothing in guilds without rank roles.
+pub async fn sync_member(
+    http: &Http,
+    client: &Client,
+    guild_id: GuildId,
+    user_id: UserId,
+    tier: Tier,
+) -> Result<()> {
+    set_rank_role(http, client, guild_id, user_id, Some(tier)).await
+}
+
+/// Takes every rank role away from a member, for when they unregister.
+pub async fn clear_member(
+    http: &Http,
+    client: &Client,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<()> {
+    set_rank_role(http, client, guild_id, user_id, None).await
+}
+
+/// Leaves a member with only the role mapped to `tier`, or with no rank role
+/// at all for `None`.
+async fn set_rank_role(
+    http: &Http,
+    client: &Client,
+    guild_id: GuildId,
+    user_id: UserId,
+    tier: Option<Tier>,
+) -> Result<()> {
+    let rank_roles = rank_roles(client, guild_id).await?;
+    if rank_roles.is_empty() {
+        return Ok(());
+    }
+
+    let member = http.get_member(guild_id.0, user_id.0).await?;
+    let wanted: Vec<RoleId> = rank_roles
+        .iter()
+        .filter(|(role_tier, _)| Some(*role_tier) == tier)
+        .map(|(_, role_id)| *role_id)
+        .collect();
+
+    for (_, role_id) in &rank_roles {
+        let has_role = member.roles.contains(role_id);
+        if wanted.contains(role_id) {
+            if !has_role {
+                http.add_member_role(guild_id.0, user_id.0, role_id.0)
+                    .await?;
+            }
+        } else if has_role {
+            http.remove_member_role(guild_id.0, user_id.0, role_id.0)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The role mapped to `tier` in a guild, if any.
+async fn rank_role(client: &Client, guild_id: GuildId, tier: Tier) -> Result<Option<RoleId>> {
+    let row = client
+        .query_opt(
+            "select role_id from rank_roles where guild_id = $1 and tier = $2",
+            &[&(guild_id.0 as i64), &tier.as_str()],
+        )
+        .await?;
+    Ok(row.map(|row| RoleId(row.get::<_, i64>(0) as u64)))
+}
+
+/// Takes a role that's no longer mapped to a tier away from the guild's
+/// registered members, since syncing only ever looks at mapped roles. Left
+/// alone if it's still mapped to another tier.
+async fn retire_role(
+    http: &Http,
+    client: &Client,
+    guild_id: GuildId,
+    role_id: RoleId,
+) -> Result<()> {
+    let still_mapped = client
+        .query_opt(
+            "select 1 from rank_roles where guild_id = $1 and role_id = $2",
+            &[&(guild_id.0 as i64), &(role_id.0 as i64)],
+        )
+        .await?
+        .is_some();
+    if still_mapped {
+        return Ok(());
+    }
+
+    let rows = client
+        .query(
+            "select discord_id from guild_users where guild_id = $1",
+            &[&(guild_id.0 as i64)],
+        )
+        .await?;
+
+    for row in rows {
+        let user_id = UserId(row.get::<_, i64>(0) as u64);
+        let result = match http.get_member(guild_id.0, user_id.0).await {
+            Ok(member) if member.roles.contains(&role_id) => {
+                http.remove_member_role(guild_id.0, user_id.0, role_id.0)
+                    .await
+            }
+            Ok(_) => Ok(()),
+            Err(why) => Err(why),
+        };
+        if let Err(why) = result {
+            println!(
+                "Failed removing old rank role {} from {} in {}: {}",
+                role_id, user_id, guild_id, why
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Syncs every registered member of every guild with rank roles to the tier
+/// of their latest stored ranked match.
+async fn sweep(http: &Http, client: &Client) -> Result<()> {
+    let rows = client
+        .query(
+            "select gu.guild_id, gu.discord_id, latest.post_csr_tier \
+             from guild_users gu \
+             join users u on u.discord_id = gu.discord_id \
+             join lateral ( \
+                 select mp.post_csr_tier \
+                 from match_players mp join matches m on m.id = mp.match_id \
+                 where mp.gamertag = u.gamertag and m.ranked and mp.post_csr_tier is not null \
+                 order by m.played_at desc limit 1 \
+             ) latest on true \
+             where exists (select 1 from rank_roles r where r.guild_id = gu.guild_id)",
+            &[],
+        )
+        .await?;
+
+    for row in rows {
+        let guild_id = GuildId(row.get::<_, i64>(0) as u64);
+        let user_id = UserId(row.get::<_, i64>(1) as u64);
+        let tier = match row.get::<_, &str>(2).parse() {
+            Ok(tier) => tier,
+            Err(_) => continue,
+        };
+
+        if let Err(why) = sync_member(http, client, guild_id, user_id, tier).await {
+            println!(
+                "Failed syncing rank role for {} in {}: {}",
+                user_id, guild_id, why
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the full sweep on `roles.sweep_interval`, starting right away.
+pub async fn sync_rank_roles(client: Arc<Client>, http: Arc<Http>, config: Arc<Config>) {
+    let mut interval = time::interval(config.roles.sweep_interval());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(why) = sweep(&http, &client).await {
+            println!("Failed syncing rank roles: {}", why);
+        }
+    }
+}
+
+pub fn create_tier_option(
+    option: &mut CreateApplicationCommandOption,
+) -> &mut CreateApplicationCommandOption {
+    option
+        .name("tier")
+        .description("The rank tier")
+        .kind(ApplicationCommandOptionType::String)
+        .required(true);
+    for tier in Tier::ALL {
+        option.add_string_choice(tier.as_str(), tier.as_str());
+    }
+    option
+}
+
+/// Lists a guild's rank roles, lowest tier first.
+async fn describe_rank_roles(client: &Client, guild_id: GuildId) -> Result<String> {
+    let mut rank_roles = rank_roles(client, guild_id).await?;
+    rank_roles.sort_by_key(|(tier, _)| *tier);
+
+    if rank_roles.is_empty() {
+        return Ok("No rank roles are set up".to_owned());
+    }
+    Ok(rank_roles
+        .iter()
+        .map(|(tier, role_id)| {
+            format!(
+                "{} {} → {}",
+                rank::rank_emoji(tier),
+                tier.as_str(),
+                role_id.mention()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// Maps a tier to a role, or clears the mapping when no role is given. A role
+/// that was mapped before is taken away from members.
+pub async fn rankrole_command(
+    command: &ApplicationCommandInteraction,
+    http: &Http,
+    client: &Client,
+) -> Reply {
+    let guild_id = match command.guild_id {
+        Some(guild_id) => guild_id,
+        None => {
+            return "This command can only be used in a server"
+                .to_owned()
+                .into()
+        }
+    };
+
+    if !commands::is_admin(command.member.as_ref()) {
+        return "Only server admins can change rank roles".to_owned().into();
+    }
+
+    let tier: Tier = match commands::option(command, "tier") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(tier)) => tier
+            .parse()
+            .unwrap_or_else(|_| unreachable!("Command type")),
+        _ => unreachable!("Command type"),
+    };
+
+    let old_role = match rank_role(client, guild_id, tier).await {
+        Ok(old_role) => old_role,
+        Err(why) => {
+            println!("Failed loading rank role: {}", why);
+            return "Couldn't save the rank role".to_owned().into();
+        }
+    };
+
+    let result = match commands::option(command, "role") {
+        Some(ApplicationCommandInteractionDataOptionValue::Role(role)) => {
+            client
+                .execute(
+                    "insert into rank_roles (guild_id, tier, role_id) values ($1, $2, $3) \
+                     on conflict (guild_id, tier) do update set role_id = EXCLUDED.role_id",
+                    &[&(guild_id.0 as i64), &tier.as_str(), &(role.id.0 as i64)],
+                )
+                .await
+        }
+        _ => {
+            client
+                .execute(
+                    "delete from rank_roles where guild_id = $1 and tier = $2",
+                    &[&(guild_id.0 as i64), &tier.as_str()],
+                )
+                .await
+        }
+    };
+    if let Err(why) = result {
+        println!("Failed saving rank role: {}", why);
+        return "Couldn't save the rank role".to_owned().into();
+    }
+
+    let new_role = match commands::option(command, "role") {
+        Some(ApplicationCommandInteractionDataOptionValue::Role(role)) => Some(role.id),
+        _ => None,
+    };
+    if let Some(old_role) = old_role.filter(|old_role| Some(*old_role) != new_role) {
+        if let Err(why) = retire_role(http, client, guild_id, old_role).await {
+            println!("Failed retiring rank role {}: {}", old_role, why);
+        }
+    }
+
+    match describe_rank_roles(client, guild_id).await {
+        Ok(description) => format!(
+            "Saved. Roles update after each ranked match and on the next sync.\n{}",
+            description
+        )
+        .into(),
+        Err(why) => {
+            println!("Failed loading rank roles: {}", why);
+            "Saved the rank role".to_owned().into()
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::commands::Reply;
+use crate::halo_api::HaloApi;
+use crate::match_response::{MatchResponse, Player};
+use crate::rank;
+use serenity::builder::{CreateComponents, CreateEmbed};
+use serenity::model::interactions::message_component::ButtonStyle;
+
+/// Discord's limit on the length of an embed field's value.
+const FIELD_LIMIT: usize = 1024;
+
+fn custom_id(match_id: &str) -> String {
+    format!("scoreboard:{}", match_id)
+}
+
+/// Parses a scoreboard button's custom ID into the match it's for.
+pub fn parse_custom_id(custom_id: &str) -> Option<&str> {
+    custom_id.strip_prefix("scoreboard:")
+}
+
+/// Adds a button under a match post that shows its full scoreboard.
+pub fn add_button<'a>(
+    components: &'a mut CreateComponents,
+    match_id: &str,
+) -> &'a mut CreateComponents {
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button
+                .style(ButtonStyle::Secondary)
+                .label("Scoreboard")
+                .custom_id(custom_id(match_id))
+        })
+    })
+}
+
+fn player_line(player: &Player) -> String {
+    let core = &player.stats.core;
+    let rank = match &player.progression {
+        Some(progression) => {
+            let csr = &progression.csr.post_match;
+            format!(
+                " · {} ({})",
+                rank::rank_label(&csr.tier, csr.sub_tier),
+                csr.value
+            )
+        }
+        None => String::new(),
+    };
+    format!(
+        "`{}` **{}** {}/{}/{} · {}/{} dmg · {:.0}%{}",
+        player.rank,
+        player.details.name,
+        core.summary.kills,
+        core.summary.deaths,
+        core.summary.assists,
+        core.damage.dealt,
+        core.damage.taken,
+        core.shots.accuracy,
+        rank
+    )
+}
+
+/// Adds `lines` as one field, continued in untitled fields if they don't fit
+/// in one.
+fn add_lines(embed: &mut CreateEmbed, name: String, lines: &[String]) {
+    let mut name = Some(name);
+    let mut value = String::new();
+    for line in lines {
+        if !value.is_empty() && value.len() + 1 + line.len() > FIELD_LIMIT {
+            embed.field(
+                name.take().unwrap_or_else(|| "\u{200b}".to_owned()),
+                &value,
+                false,
+            );
+            value.clear();
+        }
+        if !value.is_empty() {
+            value.push('\n');
+        }
+        value.push_str(line);
+    }
+    embed.field(name.unwrap_or_else(|| "\u{200b}".to_owned()), value, false);
+}
+
+/// Every player in the match, grouped by team, best team first.
+fn scoreboard_embed(match_id: &str, match_response: &MatchResponse) -> CreateEmbed {
+    let data = &match_response.data;
+    let mut teams: Vec<_> = data.teams.details.iter().collect();
+    teams.sort_by_key(|team| team.rank);
+
+    let mut embed = CreateEmbed::default();
+    embed.title("Scoreboard").color((0, 0, 255)).url(format!(
+        "https://halotracker.com/halo-infinite/match/{}",
+        match_id
+    ));
+
+    let mut players: Vec<&Player> = data.players.iter().collect();
+    players.sort_by_key(|player| player.rank);
+
+    if teams.is_empty() {
+        let lines: Vec<String> = players.iter().map(|player| player_line(player)).collect();
+        add_lines(&mut embed, "Players".to_owned(), &lines);
+        return embed;
+    }
+
+    for team in teams {
+        let lines: Vec<String> = players
+            .iter()
+            .filter(|player| player.team.id == team.team.id)
+            .map(|player| player_line(player))
+            .collect();
+        if lines.is_empty() {
+            continue;
+        }
+        let (result, _) = crate::match_card::outcome_label(&team.outcome);
+        let name = format!(
+            "{} {} · {:.0} MMR",
+            team.team.name, result, team.team.skill.mmr
+        );
+        add_lines(&mut embed, name, &lines);
+    }
+
+    embed
+}
+
+/// Only shown to whoever asked, so it doesn't clutter the channel.
+pub async fn scoreboard(api: &dyn HaloApi, match_id: &str) -> Reply {
+    let mut reply: Reply = match api.get_match(match_id).await {
+        Ok(match_response) => scoreboard_embed(match_id, &match_response).into(),
+        Err(why) => {
+            println!("Failed getting scoreboard for {}: {}", match_id, why);
+            "Couldn't get the scoreboard right now".to_owned().into()
+        }
+    };
+    reply.ephemeral = true;
+    reply
+}
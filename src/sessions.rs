@@ -0,0 +1,348 @@
+use crate::config::Config;
+use crate::error::Result;
+use crate::stats;
+use chrono::{DateTime, Duration, Utc};
+use serenity::http::Http;
+use serenity::model::id::ChannelId;
+use std::sync::Arc;
+use tokio::time::{self, MissedTickBehavior};
+use tokio_postgres::{Client, Row};
+
+/// How often to look for sessions that have ended.
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// One of a player's stored matches.
+struct SessionMatch {
+    id: String,
+    played_at: DateTime<Utc>,
+    duration_seconds: i32,
+    map_name: String,
+    category: String,
+    lobby_kills: f64,
+    outcome: String,
+    kills: i32,
+    deaths: i32,
+    assists: i32,
+    kda: f64,
+    csr_change: Option<i32>,
+}
+
+impl SessionMatch {
+    fn from_row(row: &Row) -> SessionMatch {
+        let pre_csr: Option<i32> = row.get(11);
+        let post_csr: Option<i32> = row.get(12);
+        SessionMatch {
+            id: row.get(0),
+            played_at: row.get(1),
+            duration_seconds: row.get(2),
+            map_name: row.get(3),
+            category: row.get(4),
+            lobby_kills: row.get(5),
+            outcome: row.get(6),
+            kills: row.get(7),
+            deaths: row.get(8),
+            assists: row.get(9),
+            kda: row.get(10),
+            csr_change: pre_csr.zip(post_csr).map(|(pre, post)| post - pre),
+        }
+    }
+
+    fn ended_at(&self) -> DateTime<Utc> {
+        self.played_at + Duration::seconds(self.duration_seconds as i64)
+    }
+}
+
+/// The matches of a player's oldest unsummarized session, if it's over.
+///
+/// `matches` are the player's unsummarized matches, oldest first. The
+/// session is the run of them with less than `gap` between one ending and
+/// the next starting. It's over once a later match starts after a gap, or
+/// `gap` has passed since its last match.
+fn finished_session(
+    mut matches: Vec<SessionMatch>,
+    gap: Duration,
+    now: DateTime<Utc>,
+) -> Option<Vec<SessionMatch>> {
+    let end = matches
+        .windows(2)
+        .position(|pair| pair[1].played_at - pair[0].ended_at() >= gap);
+    match end {
+        Some(last) => matches.truncate(last + 1),
+        None if now - matches.last()?.ended_at() >= gap => {}
+        None => return None,
+    }
+    Some(matches)
+}
+
+fn medal_summary(medals: &[(String, i64)]) -> String {
+    let total: i64 = medals.iter().map(|(_, count)| count).sum();
+    if total == 0 {
+        return "Nothing special 😔".to_owned();
+    }
+
+    let top: Vec<String> = medals
+        .iter()
+        .filter_map(|(name, count)| {
+            crate::match_card::name_to_emoji(name).map(|emoji| format!("{}x{}", emoji, count))
+        })
+        .take(5)
+        .collect();
+    format!("{} total {}", total, top.join(" "))
+}
+
+async fn post_summary(
+    http: &Http,
+    channels: &[ChannelId],
+    gamertag: &str,
+    session: &[SessionMatch],
+    medals: &[(String, i64)],
+) -> Result<()> {
+    let wins = session.iter().filter(|game| game.outcome == "win").count();
+    let losses = session.iter().filter(|game| game.outcome == "loss").count();
+    let csr_changes: Vec<i32> = session.iter().filter_map(|game| game.csr_change).collect();
+    let minutes = session
+        .iter()
+        .map(|game| game.duration_seconds as f64)
+        .sum::<f64>()
+        / 60.0;
+    let kills: i32 = session.iter().map(|game| game.kills).sum();
+    let lobby_kills: f64 = session.iter().map(|game| game.lobby_kills).sum();
+
+    let best = session
+        .iter()
+        .max_by(|a, b| a.kda.total_cmp(&b.kda))
+        .expect("sessions aren't empty");
+    let best = format!(
+        "{}/{}/{} ({}) on {} ({}, {})",
+        best.kills, best.deaths, best.assists, best.kda, best.map_name, best.category, best.outcome
+    );
+
+    let start = session[0].played_at.timestamp();
+    let end = session[session.len() - 1].ended_at().timestamp();
+    let color = if wins >= losses {
+        (0, 255, 0)
+    } else {
+        (255, 0, 0)
+    };
+
+    for channel in channels {
+        let result = channel
+            .send_message(http, |m| {
+                m.embed(|e| {
+                    e.title(format!("{}'s session: {} games", gamertag, session.len()))
+                        .color(color)
+                        .description(format!("<t:{}:t> – <t:{}:t>", start, end))
+                        .field("W/L", format!("{}/{}", wins, losses), true);
+                    if !csr_changes.is_empty() {
+                        let change: i32 = csr_changes.iter().sum();
+                        e.field("Net CSR", stats::format_change(change as i64), true);
+                    }
+                    e.field(
+                        "KPM / Avg",
+                        format!(
+                            "{:.1} / {:.1}",
+                            kills as f64 / minutes.max(1.0),
+                            lobby_kills / minutes.max(1.0)
+                        ),
+                        true,
+                    )
+                    .field("Best game", best.as_str(), false)
+                    .field("Medals", medal_summary(medals), false)
+                })
+            })
+            .await;
+
+        if let Err(why) = result {
+            println!("Failed sending session summary to {}: {}", channel, why);
+        }
+    }
+
+    Ok(())
+}
+
+/// Summarizes a player's latest session if it's over. Sessions too short to
+/// summarize are skipped, but still marked as summarized.
+async fn summarize_player(
+    http: &Http,
+    client: &Client,
+    config: &Config,
+    discord_id: i64,
+    gamertag: &str,
+    display_gamertag: &str,
+    summarized_until: Option<DateTime<Utc>>,
+) -> Result<()> {
+    let rows = client
+        .query(
+            "select m.id, m.played_at, m.duration_seconds, m.map_name, m.category, m.avg_kills, \
+             mp.outcome, mp.kills, mp.deaths, mp.assists, mp.kda, mp.pre_csr, mp.post_csr \
+             from match_players mp join matches m on m.id = mp.match_id \
+             where mp.gamertag = $1 and ($2::timestamptz is null or m.played_at > $2) \
+             order by m.played_at",
+            &[&gamertag, &summarized_until],
+        )
+        .await?;
+    let matches: Vec<SessionMatch> = rows.iter().map(SessionMatch::from_row).collect();
+
+    let session = match finished_session(matches, config.sessions.gap(), Utc::now()) {
+        Some(session) => session,
+        None => return Ok(()),
+    };
+    let last_played_at = session[session.len() - 1].played_at;
+
+    if session.len() >= config.sessions.min_games {
+        let match_ids: Vec<&str> = session.iter().map(|game| game.id.as_str()).collect();
+        let medals = client
+            .query(
+                "select name, sum(count) from match_player_medals \
+                 where gamertag = $1 and match_id = any($2) \
+                 group by name order by 2 desc, name",
+                &[&gamertag, &match_ids],
+            )
+            .await?;
+        let medals: Vec<(String, i64)> =
+            medals.iter().map(|row| (row.get(0), row.get(1))).collect();
+
+        let channels = client
+            .query(
+                "select g.channel_id from guilds g \
+                 join guild_users gu on gu.guild_id = g.guild_id \
+                 where gu.discord_id = $1",
+                &[&discord_id],
+            )
+            .await?;
+        let channels: Vec<ChannelId> = channels
+            .iter()
+            .map(|row| ChannelId(row.get::<_, i64>(0) as u64))
+            .collect();
+
+        post_summary(http, &channels, display_gamertag, &session, &medals).await?;
+    }
+
+    client
+        .execute(
+            "update users set session_summarized_until = $2 where discord_id = $1",
+            &[&discord_id, &last_played_at],
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn check_sessions(http: &Http, client: &Client, config: &Config) -> Result<()> {
+    let rows = client
+        .query(
+            "select u.discord_id, u.gamertag, coalesce(u.display_gamertag, u.gamertag), \
+             u.session_summarized_until from users u \
+             where u.enabled and exists ( \
+                 select 1 from match_players mp join matches m on m.id = mp.match_id \
+                 where mp.gamertag = u.gamertag \
+                 and (u.session_summarized_until is null or m.played_at > u.session_summarized_until) \
+             )",
+            &[],
+        )
+        .await?;
+
+    for row in rows {
+        let gamertag: &str = row.get(1);
+        let result = summarize_player(
+            http,
+            client,
+            config,
+            row.get(0),
+            gamertag,
+            row.get(2),
+            row.get(3),
+        )
+        .await;
+        if let Err(why) = result {
+            println!("Failed summarizing session for {}: {}", gamertag, why);
+        }
+    }
+
+    Ok(())
+}
+
+/// Posts a summary for each player's session once they've gone
+/// `sessions.gap_mins` without a match.
+pub async fn summarize_sessions(client: Arc<Client>, http: Arc<Http>, config: Arc<Config>) {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(why) = check_sessions(&http, &client, &config).await {
+            println!("Failed checking for finished sessions: {}", why);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(mins: i64) -> DateTime<Utc> {
+        Utc.ymd(2022, 2, 12).and_hms(18, 0, 0) + Duration::minutes(mins)
+    }
+
+    /// A ten minute match starting `start` minutes in.
+    fn game(id: &str, start: i64) -> SessionMatch {
+        SessionMatch {
+            id: id.to_owned(),
+            played_at: at(start),
+            duration_seconds: 600,
+            map_name: "Streets".to_owned(),
+            category: "Strongholds".to_owned(),
+            lobby_kills: 10.0,
+            outcome: "win".to_owned(),
+            kills: 10,
+            deaths: 5,
+            assists: 3,
+            kda: 8.0,
+            csr_change: Some(10),
+        }
+    }
+
+    fn ids(session: Option<Vec<SessionMatch>>) -> Option<Vec<String>> {
+        session.map(|session| session.into_iter().map(|game| game.id).collect())
+    }
+
+    #[test]
+    fn a_gap_ends_the_session_before_it() {
+        let matches = vec![game("a", 0), game("b", 15), game("c", 90), game("d", 100)];
+
+        assert_eq!(
+            ids(finished_session(matches, Duration::minutes(30), at(110))),
+            Some(vec!["a".to_owned(), "b".to_owned()])
+        );
+    }
+
+    #[test]
+    fn a_session_still_going_isnt_finished() {
+        let matches = vec![game("a", 0), game("b", 15)];
+
+        assert_eq!(
+            ids(finished_session(matches, Duration::minutes(30), at(40))),
+            None
+        );
+    }
+
+    #[test]
+    fn a_session_finishes_once_the_gap_has_passed() {
+        let matches = vec![game("a", 0), game("b", 15)];
+
+        assert_eq!(
+            ids(finished_session(matches, Duration::minutes(30), at(55))),
+            Some(vec!["a".to_owned(), "b".to_owned()])
+        );
+    }
+
+    #[test]
+    fn no_matches_is_no_session() {
+        assert_eq!(
+            ids(finished_session(Vec::new(), Duration::minutes(30), at(0))),
+            None
+        );
+    }
+}
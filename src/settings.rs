@@ -0,0 +1,368 @@
+use crate::commands::Reply;
+use crate::error::Result;
+use crate::matches_response::Data;
+use serenity::builder::CreateComponents;
+use serenity::model::id::UserId;
+use serenity::model::interactions::message_component::MessageComponentInteraction;
+use tokio_postgres::{Client, Row};
+
+/// One of the multiple-choice settings on `/settings`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Setting {
+    Categories,
+    Queues,
+    Inputs,
+    Outcomes,
+}
+
+impl Setting {
+    const ALL: [Setting; 4] = [
+        Setting::Categories,
+        Setting::Queues,
+        Setting::Inputs,
+        Setting::Outcomes,
+    ];
+
+    /// Also the setting's column in `user_settings`.
+    fn as_str(&self) -> &'static str {
+        match self {
+            Setting::Categories => "categories",
+            Setting::Queues => "queues",
+            Setting::Inputs => "inputs",
+            Setting::Outcomes => "outcomes",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<Setting> {
+        Setting::ALL
+            .iter()
+            .copied()
+            .find(|setting| setting.as_str() == value)
+    }
+
+    fn placeholder(&self) -> &'static str {
+        match self {
+            Setting::Categories => "Playlists to post",
+            Setting::Queues => "Ranked queues to post",
+            Setting::Inputs => "Ranked inputs to post",
+            Setting::Outcomes => "Results to post",
+        }
+    }
+
+    /// Every value the setting can take, with its label. The values match
+    /// what's stored in the match history.
+    fn choices(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Setting::Categories => &[
+                ("ranked", "Ranked"),
+                ("social", "Social"),
+                ("custom", "Custom games"),
+            ],
+            Setting::Queues => &[("solo-duo", "Solo/Duo"), ("open", "Open")],
+            Setting::Inputs => &[
+                ("controller", "Controller"),
+                ("mnk", "M+K"),
+                ("crossplay", "Crossplay"),
+            ],
+            Setting::Outcomes => &[
+                ("win", "Wins"),
+                ("loss", "Losses"),
+                ("draw", "Draws"),
+                ("left", "Games I left"),
+            ],
+        }
+    }
+
+    /// What's posted before a player changes anything: ranked games they
+    /// didn't leave.
+    fn defaults(&self) -> Vec<String> {
+        self.choices()
+            .iter()
+            .map(|(value, _)| *value)
+            .filter(|value| match self {
+                Setting::Categories => *value == "ranked",
+                Setting::Outcomes => *value != "left",
+                _ => true,
+            })
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+/// Which of a player's matches get announced.
+#[derive(Debug, Clone)]
+pub struct Filters {
+    categories: Vec<String>,
+    queues: Vec<String>,
+    inputs: Vec<String>,
+    outcomes: Vec<String>,
+}
+
+impl Default for Filters {
+    fn default() -> Filters {
+        Filters {
+            categories: Setting::Categories.defaults(),
+            queues: Setting::Queues.defaults(),
+            inputs: Setting::Inputs.defaults(),
+            outcomes: Setting::Outcomes.defaults(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Filters {
+    /// Keeps every match, custom games included.
+    pub fn all() -> Filters {
+        let all = |setting: Setting| -> Vec<String> {
+            setting
+                .choices()
+                .iter()
+                .map(|(value, _)| (*value).to_owned())
+                .collect()
+        };
+        Filters {
+            categories: all(Setting::Categories),
+            queues: all(Setting::Queues),
+            inputs: all(Setting::Inputs),
+            outcomes: all(Setting::Outcomes),
+        }
+    }
+}
+
+impl Filters {
+    /// Reads the four `user_settings` columns starting at column `first`,
+    /// using the default for any that are null.
+    pub fn from_row(row: &Row, first: usize) -> Filters {
+        let column = |offset: usize, setting: Setting| {
+            row.get::<_, Option<Vec<String>>>(first + offset)
+                .unwrap_or_else(|| setting.defaults())
+        };
+        Filters {
+            categories: column(0, Setting::Categories),
+            queues: column(1, Setting::Queues),
+            inputs: column(2, Setting::Inputs),
+            outcomes: column(3, Setting::Outcomes),
+        }
+    }
+
+    fn values(&self, setting: Setting) -> &[String] {
+        match setting {
+            Setting::Categories => &self.categories,
+            Setting::Queues => &self.queues,
+            Setting::Inputs => &self.inputs,
+            Setting::Outcomes => &self.outcomes,
+        }
+    }
+
+    fn allows(&self, setting: Setting, value: &str) -> bool {
+        self.values(setting).iter().any(|allowed| allowed == value)
+    }
+
+    /// Whether a match should be announced. Queue and input only apply to
+    /// playlists that have them.
+    pub fn keeps(&self, data: &Data) -> bool {
+        let properties = data
+            .details
+            .playlist
+            .as_ref()
+            .map(|playlist| &playlist.properties);
+        let category = match properties {
+            Some(properties) if properties.ranked => "ranked",
+            Some(_) => "social",
+            None => "custom",
+        };
+        let queue = properties.and_then(|properties| properties.queue.as_ref());
+        let input = properties.and_then(|properties| properties.input.as_ref());
+
+        self.allows(Setting::Categories, category)
+            && queue.is_none_or(|queue| self.allows(Setting::Queues, queue.as_str()))
+            && input.is_none_or(|input| self.allows(Setting::Inputs, input.as_str()))
+            && self.allows(Setting::Outcomes, data.player.outcome.as_str())
+    }
+}
+
+/// A registered player's filters, or `None` if they aren't registered.
+async fn load(client: &Client, user_id: UserId) -> Result<Option<Filters>> {
+    let row = client
+        .query_opt(
+            "select s.categories, s.queues, s.inputs, s.outcomes \
+             from users u left join user_settings s on s.discord_id = u.discord_id \
+             where u.discord_id = $1",
+            &[&(user_id.0 as i64)],
+        )
+        .await?;
+    Ok(row.map(|row| Filters::from_row(&row, 0)))
+}
+
+async fn save(client: &Client, user_id: UserId, setting: Setting, values: &[String]) -> Result<()> {
+    client
+        .execute(
+            format!(
+                "insert into user_settings (discord_id, {0}) values ($1, $2) \
+                 on conflict (discord_id) do update set {0} = EXCLUDED.{0}",
+                setting.as_str()
+            )
+            .as_str(),
+            &[&(user_id.0 as i64), &values],
+        )
+        .await?;
+    Ok(())
+}
+
+fn custom_id(setting: Setting) -> String {
+    format!("settings:{}", setting.as_str())
+}
+
+/// Parses a settings menu's custom ID back into the setting it edits.
+pub fn parse_custom_id(custom_id: &str) -> Option<Setting> {
+    Setting::from_str(custom_id.strip_prefix("settings:")?)
+}
+
+/// A select menu per setting, with the player's current choices selected.
+fn settings_reply(filters: &Filters, content: &str) -> Reply {
+    let mut components = CreateComponents::default();
+    for setting in Setting::ALL {
+        components.create_action_row(|row| {
+            row.create_select_menu(|menu| {
+                menu.custom_id(custom_id(setting))
+                    .placeholder(setting.placeholder())
+                    .min_values(1)
+                    .max_values(setting.choices().len() as u64)
+                    .options(|options| {
+                        for (value, label) in setting.choices() {
+                            options.create_option(|option| {
+                                option
+                                    .label(label)
+                                    .value(value)
+                                    .default_selection(filters.allows(setting, value))
+                            });
+                        }
+                        options
+                    })
+            })
+        });
+    }
+
+    Reply {
+        content: Some(content.to_owned()),
+        components: Some(components),
+        ephemeral: true,
+        ..Default::default()
+    }
+}
+
+pub async fn settings_command(user_id: UserId, client: &Client) -> Reply {
+    match load(client, user_id).await {
+        Ok(Some(filters)) => settings_reply(&filters, "Choose which of your matches get posted"),
+        Ok(None) => "You aren't registered yet, use /register first"
+            .to_owned()
+            .into(),
+        Err(why) => {
+            println!("Failed loading settings: {}", why);
+            "Couldn't load your settings".to_owned().into()
+        }
+    }
+}
+
+/// Saves a change made in one of the settings menus.
+pub async fn update(
+    component: &MessageComponentInteraction,
+    client: &Client,
+    setting: Setting,
+) -> Reply {
+    let user_id = component.user.id;
+    let values: Vec<String> = component
+        .data
+        .values
+        .iter()
+        .filter(|value| setting.choices().iter().any(|(choice, _)| choice == value))
+        .cloned()
+        .collect();
+
+    if let Err(why) = save(client, user_id, setting, &values).await {
+        println!("Failed saving settings: {}", why);
+        return "Couldn't save your settings".to_owned().into();
+    }
+
+    match load(client, user_id).await {
+        Ok(Some(filters)) => {
+            settings_reply(&filters, "Saved, choose which of your matches get posted")
+        }
+        Ok(None) => "You aren't registered yet, use /register first"
+            .to_owned()
+            .into(),
+        Err(why) => {
+            println!("Failed loading settings: {}", why);
+            "Couldn't load your settings".to_owned().into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A recorded ranked Open Crossplay win, with its playlist replaced.
+    fn game(playlist: serde_json::Value, outcome: &str) -> Data {
+        let recorded: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string("fixtures/matches/billzabob.json").unwrap(),
+        )
+        .unwrap();
+        let mut data = recorded["data"][0].clone();
+        data["details"]["playlist"] = playlist;
+        data["player"]["outcome"] = outcome.into();
+        serde_json::from_value(data).unwrap()
+    }
+
+    fn ranked(queue: &str, input: &str) -> serde_json::Value {
+        serde_json::json!({
+            "name": "Ranked Arena",
+            "properties": { "queue": queue, "input": input, "ranked": true },
+        })
+    }
+
+    fn social() -> serde_json::Value {
+        serde_json::json!({
+            "name": "Quick Play",
+            "properties": { "queue": null, "input": null, "ranked": false },
+        })
+    }
+
+    #[test]
+    fn defaults_keep_ranked_games_that_werent_left() {
+        let filters = Filters::default();
+
+        assert!(filters.keeps(&game(ranked("open", "crossplay"), "win")));
+        assert!(filters.keeps(&game(ranked("solo-duo", "mnk"), "loss")));
+        assert!(!filters.keeps(&game(ranked("open", "crossplay"), "left")));
+        assert!(!filters.keeps(&game(social(), "win")));
+        assert!(!filters.keeps(&game(serde_json::Value::Null, "win")));
+    }
+
+    #[test]
+    fn queue_and_input_only_apply_to_playlists_that_have_them() {
+        let filters = Filters {
+            categories: vec!["ranked".to_owned(), "social".to_owned()],
+            queues: vec!["solo-duo".to_owned()],
+            inputs: vec!["controller".to_owned()],
+            ..Filters::default()
+        };
+
+        assert!(filters.keeps(&game(ranked("solo-duo", "controller"), "win")));
+        assert!(!filters.keeps(&game(ranked("open", "controller"), "win")));
+        assert!(!filters.keeps(&game(ranked("solo-duo", "mnk"), "win")));
+        assert!(filters.keeps(&game(social(), "win")));
+    }
+
+    #[test]
+    fn outcomes_are_filtered_for_every_category() {
+        let filters = Filters {
+            outcomes: vec!["win".to_owned()],
+            ..Filters::all()
+        };
+
+        assert!(filters.keeps(&game(serde_json::Value::Null, "win")));
+        assert!(!filters.keeps(&game(serde_json::Value::Null, "loss")));
+        assert!(!filters.keeps(&game(ranked("open", "crossplay"), "draw")));
+    }
+}
@@ -0,0 +1,271 @@
+use crate::commands::{self, Reply};
+use crate::config::Config;
+use crate::error::Result;
+use crate::match_card;
+use chrono::{DateTime, Duration, Utc};
+use serenity::builder::{CreateApplicationCommandOption, CreateEmbed};
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+    ApplicationCommandOptionType,
+};
+use tokio_postgres::Client;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Day,
+    Week,
+    Season,
+    All,
+}
+
+impl Period {
+    pub fn from_option(value: Option<&str>) -> Period {
+        match value {
+            Some("day") => Period::Day,
+            Some("season") => Period::Season,
+            Some("all") => Period::All,
+            _ => Period::Week,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Season => "season",
+            Period::All => "all",
+        }
+    }
+
+    /// The earliest match included in the period, or `None` for all time.
+    pub fn since(&self, config: &Config) -> Option<DateTime<Utc>> {
+        match self {
+            Period::Day => Some(Utc::now() - Duration::days(1)),
+            Period::Week => Some(Utc::now() - Duration::weeks(1)),
+            Period::Season => Some(config.stats.season_start),
+            Period::All => None,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Period::Day => "the last day",
+            Period::Week => "the last week",
+            Period::Season => "this season",
+            Period::All => "all time",
+        }
+    }
+
+    /// A `period` option offering every period, for commands that take one.
+    pub fn create_option(
+        option: &mut CreateApplicationCommandOption,
+    ) -> &mut CreateApplicationCommandOption {
+        option
+            .name("period")
+            .description("How far back to look (defaults to the last week)")
+            .kind(ApplicationCommandOptionType::String)
+            .add_string_choice("Day", "day")
+            .add_string_choice("Week", "week")
+            .add_string_choice("Season", "season")
+            .add_string_choice("All time", "all")
+    }
+}
+
+/// A player's aggregate performance over a period of stored match history.
+#[derive(Debug)]
+pub struct PlayerStats {
+    pub games: i64,
+    pub wins: i64,
+    pub losses: i64,
+    pub kills: i64,
+    pub deaths: i64,
+    pub assists: i64,
+    pub avg_kda: f64,
+    pub minutes: f64,
+    pub avg_accuracy: f64,
+    pub avg_damage: f64,
+    pub lobby_avg_damage: f64,
+    pub lobby_kills: f64,
+    /// Net CSR change in each ranked playlist played, labelled like
+    /// `/graph`, most recently played first. Each queue and input is its
+    /// own ladder, so they aren't added together.
+    pub csr_changes: Vec<(String, i64)>,
+}
+
+impl PlayerStats {
+    pub fn win_rate(&self) -> f64 {
+        100.0 * self.wins as f64 / self.games.max(1) as f64
+    }
+
+    pub fn kpm(&self) -> f64 {
+        self.kills as f64 / self.minutes.max(1.0)
+    }
+
+    pub fn lobby_kpm(&self) -> f64 {
+        self.lobby_kills / self.minutes.max(1.0)
+    }
+}
+
+pub async fn player_stats(
+    client: &Client,
+    gamertag: &str,
+    since: Option<DateTime<Utc>>,
+) -> Result<Option<PlayerStats>> {
+    let row = client
+        .query_one(
+            "select count(*), \
+             count(*) filter (where mp.outcome = 'win'), \
+             count(*) filter (where mp.outcome = 'loss'), \
+             coalesce(sum(mp.kills), 0), \
+             coalesce(sum(mp.deaths), 0), \
+             coalesce(sum(mp.assists), 0), \
+             coalesce(avg(mp.kda), 0), \
+             coalesce(sum(m.duration_seconds), 0) / 60.0::float8, \
+             coalesce(avg(mp.accuracy), 0), \
+             coalesce(avg(mp.damage_dealt), 0)::float8, \
+             coalesce(avg(m.avg_damage), 0), \
+             coalesce(sum(m.avg_kills), 0) \
+             from match_players mp join matches m on m.id = mp.match_id \
+             where mp.gamertag = $1 and ($2::timestamptz is null or m.played_at >= $2)",
+            &[&gamertag.to_lowercase(), &since],
+        )
+        .await?;
+
+    let games: i64 = row.get(0);
+    if games == 0 {
+        return Ok(None);
+    }
+
+    let csr_changes = client
+        .query(
+            "select m.queue, m.input, coalesce(sum(mp.post_csr - mp.pre_csr), 0) \
+             from match_players mp join matches m on m.id = mp.match_id \
+             where mp.gamertag = $1 and ($2::timestamptz is null or m.played_at >= $2) \
+             and m.ranked and mp.post_csr is not null \
+             group by m.queue, m.input order by max(m.played_at) desc",
+            &[&gamertag.to_lowercase(), &since],
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            let label = match_card::ranked_playlist_label(row.get(0), row.get(1));
+            (label, row.get(2))
+        })
+        .collect();
+
+    Ok(Some(PlayerStats {
+        games,
+        wins: row.get(1),
+        losses: row.get(2),
+        kills: row.get(3),
+        deaths: row.get(4),
+        assists: row.get(5),
+        avg_kda: row.get(6),
+        minutes: row.get(7),
+        avg_accuracy: row.get(8),
+        avg_damage: row.get(9),
+        lobby_avg_damage: row.get(10),
+        lobby_kills: row.get(11),
+        csr_changes,
+    }))
+}
+
+/// One line per ranked playlist, or a note that there were none.
+fn csr_changes_text(csr_changes: &[(String, i64)]) -> String {
+    if csr_changes.is_empty() {
+        return "No ranked games".to_owned();
+    }
+    csr_changes
+        .iter()
+        .map(|(playlist, change)| format!("{} {}", playlist, format_change(*change)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn format_change(change: i64) -> String {
+    if change > 0 {
+        format!("+{}", change)
+    } else {
+        change.to_string()
+    }
+}
+
+pub async fn stats_command(
+    command: &ApplicationCommandInteraction,
+    client: &Client,
+    config: &Config,
+) -> Reply {
+    let user = match commands::option(command, "user") {
+        Some(ApplicationCommandInteractionDataOptionValue::User(user, _)) => user,
+        _ => &command.user,
+    };
+    let period = match commands::option(command, "period") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(period)) => {
+            Period::from_option(Some(period))
+        }
+        _ => Period::from_option(None),
+    };
+
+    let gamertag = match commands::gamertag_for(client, user.id).await {
+        Ok(Some(gamertag)) => gamertag,
+        Ok(None) => return format!("{} hasn't registered a gamertag", user.name).into(),
+        Err(why) => {
+            println!("Failed looking up gamertag: {}", why);
+            return "Couldn't look up that player".to_owned().into();
+        }
+    };
+
+    let stats = match player_stats(client, &gamertag, period.since(config)).await {
+        Ok(Some(stats)) => stats,
+        Ok(None) => {
+            return format!("{} has no stored games for {}", gamertag, period.describe()).into()
+        }
+        Err(why) => {
+            println!("Failed getting stats for {}: {}", gamertag, why);
+            return "Couldn't get stats right now".to_owned().into();
+        }
+    };
+
+    let color = if stats.wins >= stats.losses {
+        (0, 255, 0)
+    } else {
+        (255, 0, 0)
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("{}'s stats for {}", gamertag, period.describe()))
+        .color(color)
+        .field("Games", stats.games, true)
+        .field(
+            "W/L",
+            format!("{}/{} ({:.0}%)", stats.wins, stats.losses, stats.win_rate()),
+            true,
+        )
+        .field("CSR change", csr_changes_text(&stats.csr_changes), true)
+        .field(
+            "KDA",
+            format!(
+                "{}/{}/{} ({:.1} avg)",
+                stats.kills, stats.deaths, stats.assists, stats.avg_kda
+            ),
+            true,
+        )
+        .field(
+            "KPM / Avg",
+            format!("{:.1} / {:.1}", stats.kpm(), stats.lobby_kpm()),
+            true,
+        )
+        .field("Accuracy", format!("{}%", stats.avg_accuracy.round()), true)
+        .field(
+            "Damage Dealt / Avg",
+            format!(
+                "{} / {}",
+                stats.avg_damage.round(),
+                stats.lobby_avg_damage.round()
+            ),
+            true,
+        );
+
+    embed.into()
+}
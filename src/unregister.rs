@@ -0,0 +1,237 @@
+use crate::commands::{self, Reply};
+use crate::error::Result;
+use crate::role_sync;
+use serenity::builder::CreateComponents;
+use serenity::http::Http;
+use serenity::model::guild::Member;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+};
+use serenity::model::interactions::message_component::{ButtonStyle, MessageComponentInteraction};
+use serenity::model::misc::Mentionable;
+use tokio_postgres::Client;
+
+/// Deletes a user, their server memberships and every stored or queued line
+/// of theirs. Matches no other registered player appears in go too, along with their
+/// teams. Returns the deleted gamertag and the servers they were in, or
+/// `None` if the user wasn't registered.
+async fn delete_user(client: &Client, user_id: UserId) -> Result<Option<(String, Vec<GuildId>)>> {
+    // Every part of a statement sees the same snapshot, so the other players
+    // still show up in `match_players` and have to be excluded by gamertag.
+    let row = client
+        .query_one(
+            "with deleted_user as ( \
+                 delete from users where discord_id = $1 returning gamertag \
+             ), deleted_players as ( \
+                 delete from match_players \
+                 where gamertag in (select gamertag from deleted_user) \
+                 returning match_id \
+             ), deleted_pending as ( \
+                 delete from pending_match_players \
+                 where lower(gamertag) in (select gamertag from deleted_user) \
+             ), deleted_matches as ( \
+                 delete from matches m \
+                 where m.id in (select match_id from deleted_players) \
+                 and not exists ( \
+                     select 1 from match_players mp \
+                     where mp.match_id = m.id \
+                     and mp.gamertag not in (select gamertag from deleted_user) \
+                 ) \
+                 returning id \
+             ) \
+             select (select gamertag from deleted_user), \
+                 array(select guild_id from guild_users where discord_id = $1)",
+            &[&(user_id.0 as i64)],
+        )
+        .await?;
+    let gamertag: Option<String> = row.get(0);
+    Ok(gamertag.map(|gamertag| {
+        let guild_ids = row
+            .get::<_, Vec<i64>>(1)
+            .into_iter()
+            .map(|guild_id| GuildId(guild_id as u64))
+            .collect();
+        (gamertag, guild_ids)
+    }))
+}
+
+async fn in_guild(client: &Client, guild_id: GuildId, user_id: UserId) -> Result<bool> {
+    let row = client
+        .query_opt(
+            "select 1 from guild_users where guild_id = $1 and discord_id = $2",
+            &[&(guild_id.0 as i64), &(user_id.0 as i64)],
+        )
+        .await?;
+    Ok(row.is_some())
+}
+
+/// Whether `member` may unregister `user_id`: anyone can remove themselves,
+/// and server admins can remove anyone registered in their server.
+async fn can_remove(
+    client: &Client,
+    user_id: UserId,
+    requester: UserId,
+    member: Option<&Member>,
+    guild_id: Option<GuildId>,
+) -> Result<bool> {
+    if user_id == requester {
+        return Ok(true);
+    }
+    match guild_id {
+        Some(guild_id) if commands::is_admin(member) => in_guild(client, guild_id, user_id).await,
+        _ => Ok(false),
+    }
+}
+
+fn custom_id(confirmed: bool, user_id: UserId) -> String {
+    let action = if confirmed { "confirm" } else { "cancel" };
+    format!("unregister:{}:{}", action, user_id.0)
+}
+
+/// Parses a confirmation button's custom ID into whether it confirms the
+/// deletion and whose data it deletes.
+pub fn parse_custom_id(custom_id: &str) -> Option<(bool, UserId)> {
+    let mut parts = custom_id.strip_prefix("unregister:")?.split(':');
+    let confirmed = match parts.next()? {
+        "confirm" => true,
+        "cancel" => false,
+        _ => return None,
+    };
+    let user_id = parts.next()?.parse().ok()?;
+    Some((confirmed, UserId(user_id)))
+}
+
+/// Asks for confirmation before anything is deleted.
+pub async fn unregister_command(command: &ApplicationCommandInteraction, client: &Client) -> Reply {
+    let user = match commands::option(command, "user") {
+        Some(ApplicationCommandInteractionDataOptionValue::User(user, _)) => user,
+        _ => &command.user,
+    };
+
+    let allowed = can_remove(
+        client,
+        user.id,
+        command.user.id,
+        command.member.as_ref(),
+        command.guild_id,
+    )
+    .await;
+    match allowed {
+        Ok(true) => {}
+        Ok(false) => {
+            return Reply {
+                ephemeral: true,
+                .."Only server admins can unregister other members of their server"
+                    .to_owned()
+                    .into()
+            }
+        }
+        Err(why) => {
+            println!("Failed checking guild membership: {}", why);
+            return "Couldn't look up that player".to_owned().into();
+        }
+    }
+
+    let gamertag = match commands::gamertag_for(client, user.id).await {
+        Ok(Some(gamertag)) => gamertag,
+        Ok(None) => {
+            return Reply {
+                ephemeral: true,
+                ..format!("{} hasn't registered a gamertag", user.mention()).into()
+            }
+        }
+        Err(why) => {
+            println!("Failed looking up gamertag: {}", why);
+            return "Couldn't look up that player".to_owned().into();
+        }
+    };
+
+    let mut components = CreateComponents::default();
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button
+                .style(ButtonStyle::Danger)
+                .label("Delete")
+                .custom_id(custom_id(true, user.id))
+        })
+        .create_button(|button| {
+            button
+                .style(ButtonStyle::Secondary)
+                .label("Cancel")
+                .custom_id(custom_id(false, user.id))
+        })
+    });
+
+    Reply {
+        content: Some(format!(
+            "This will unregister {} ({}) from every server and delete all of their stored \
+             match history. This can't be undone.",
+            user.mention(),
+            gamertag
+        )),
+        components: Some(components),
+        ephemeral: true,
+        ..Default::default()
+    }
+}
+
+/// Handles a press of one of the confirmation buttons. Permissions are
+/// checked again, since the button only carries whose data to delete.
+pub async fn confirm(
+    component: &MessageComponentInteraction,
+    http: &Http,
+    client: &Client,
+    confirmed: bool,
+    user_id: UserId,
+) -> Reply {
+    let reply = |content: String| Reply {
+        components: Some(CreateComponents::default()),
+        ..content.into()
+    };
+
+    if !confirmed {
+        return reply("Nothing was deleted".to_owned());
+    }
+
+    let allowed = can_remove(
+        client,
+        user_id,
+        component.user.id,
+        component.member.as_ref(),
+        component.guild_id,
+    )
+    .await;
+    match allowed {
+        Ok(true) => {}
+        Ok(false) => return reply("You can't unregister that member".to_owned()),
+        Err(why) => {
+            println!("Failed checking guild membership: {}", why);
+            return reply("Couldn't unregister that player".to_owned());
+        }
+    }
+
+    match delete_user(client, user_id).await {
+        Ok(Some((gamertag, guild_ids))) => {
+            // The rank role mappings outlive the user, so their roles can
+            // still be looked up and taken away.
+            for guild_id in guild_ids {
+                if let Err(why) = role_sync::clear_member(http, client, guild_id, user_id).await {
+                    println!(
+                        "Failed removing rank roles from {} in {}: {}",
+                        user_id, guild_id, why
+                    );
+                }
+            }
+            reply(format!(
+                "Unregistered {} and deleted their match history",
+                gamertag
+            ))
+        }
+        Ok(None) => reply(format!("{} isn't registered", user_id.mention())),
+        Err(why) => {
+            println!("Failed unregistering {}: {}", user_id, why);
+            reply("Couldn't unregister that player".to_owned())
+        }
+    }
+}